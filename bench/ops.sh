@@ -0,0 +1,28 @@
+#!/bin/sh
+# Reports the time and allocations per operation for the loops in
+# ops/ops.lox, after taking away the cost of an empty loop. Takes the same
+# arguments and FLAGS as run.sh, but a binary given to it must be built with
+# --features count-allocs:
+#
+#   bench/ops.sh
+#   bench/ops.sh /tmp/old/lox-ast
+#   FLAGS=--vm bench/ops.sh
+
+cd "$(dirname "$0")" || exit 1
+LOX=${1:-../target/release/lox-ast}
+FLAGS=${FLAGS:-}
+
+if [ "$#" -eq 0 ]; then
+    cargo build --release --quiet --features count-allocs || exit 1
+fi
+
+"$LOX" $FLAGS ops/ops.lox | awk '
+    NF == 1 { n = $1; next }
+    { name[++count] = $1; ms[count] = $2; allocs[count] = $3 }
+    END {
+        printf "%-12s %8s %10s\n", "operation", "ns/op", "allocs/op"
+        for (i = 2; i <= count; i++) {
+            printf "%-12s %8.1f %10.2f\n", name[i],
+                (ms[i] - ms[1]) * 1000000 / n, (allocs[i] - allocs[1]) / n
+        }
+    }'
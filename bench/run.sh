@@ -0,0 +1,31 @@
+#!/bin/sh
+# Runs each benchmark and reports the best of several timings, as printed by
+# the script itself in milliseconds. Pass a different interpreter binary to
+# compare builds:
+#
+#   bench/run.sh                       # uses target/release/lox-ast
+#   bench/run.sh /tmp/old/lox-ast
+#   RUNS=10 bench/run.sh
+#   FLAGS=--vm bench/run.sh            # bytecode VM instead of the tree-walker
+
+cd "$(dirname "$0")" || exit 1
+LOX=${1:-../target/release/lox-ast}
+RUNS=${RUNS:-5}
+FLAGS=${FLAGS:-}
+
+if [ "$#" -eq 0 ]; then
+    cargo build --release --quiet || exit 1
+fi
+
+for script in *.lox; do
+    best=""
+    i=0
+    while [ "$i" -lt "$RUNS" ]; do
+        elapsed=$("$LOX" $FLAGS "$script" | tail -n 1)
+        if [ -z "$best" ] || awk "BEGIN { exit !($elapsed < $best) }"; then
+            best=$elapsed
+        fi
+        i=$((i + 1))
+    done
+    printf "%-10s %6d ms\n" "${script%.lox}" "$best"
+done
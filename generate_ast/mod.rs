@@ -12,36 +12,43 @@ pub fn generate_ast(output_dir: &str) -> io::Result<()> {
     define_ast(
         output_dir,
         "Expr",
-        &["error", "token", "object", "rc"],
+        &["error", "environment", "inline_cache", "token", "object", "cell", "rc"],
         &[
-            "Assign   : Token name, Rc<Expr> value",
+            "Assign   : Token name, Rc<Expr> value, Cell<Option<Slot>> slot",
             "Binary   : Rc<Expr> left, Token operator, Rc<Expr> right",
-            "Call     : Rc<Expr> callee, Token paren, Vec<Rc<Expr>> arguments",
-            "Get      : Rc<Expr> object, Token name",
+            "Call     : Rc<Expr> callee, Token paren, Vec<Rc<Expr>> arguments, Vec<Option<Token>> names, Cell<bool> tail",
+            "Error    : Token token",
+            "Get      : Rc<Expr> object, Token name, InlineCache cache",
             "Grouping : Rc<Expr> expression",
+            "Index    : Rc<Expr> object, Token bracket, Rc<Expr> index",
             "Literal  : Option<Object> value",
             "Logical  : Rc<Expr> left, Token operator, Rc<Expr> right",
-            "Set      : Rc<Expr> object, Token name, Rc<Expr> value",
-            "Super    : Token keyword, Token method",
-            "This     : Token keyword",
+            "Set      : Rc<Expr> object, Token name, Rc<Expr> value, InlineCache cache",
+            "Super    : Token keyword, Token method, Cell<Option<Slot>> slot",
+            "This     : Token keyword, Cell<Option<Slot>> slot",
             "Unary    : Token operator, Rc<Expr> right",
-            "Variable : Token name",
+            "Variable : Token name, Cell<Option<Slot>> slot",
         ],
     )?;
     define_ast(
         output_dir,
         "Stmt",
-        &["error", "expr", "token", "rc"],
+        &["error", "expr", "pattern", "token", "rc"],
         &[
             "Block      : Rc<Vec<Rc<Stmt>>> statements",
-            "Class      : Token name, Option<Rc<Expr>> superclass, Rc<Vec<Rc<Stmt>>> methods",
+            "Class      : Token name, Option<Rc<Expr>> superclass, Rc<Vec<Rc<Expr>>> traits, Rc<Vec<Rc<Stmt>>> methods, Rc<Vec<Rc<Stmt>>> class_methods, Rc<Vec<Rc<Stmt>>> setters",
             "Break      : Token token",
+            "Do         : Rc<Stmt> body, Rc<Expr> condition",
+            "Error      : Rc<Vec<Token>> tokens",
             "Expression : Rc<Expr> expression",
-            "Function   : Token name, Rc<Vec<Token>> params, Rc<Vec<Rc<Stmt>>> body",
+            "ForIn      : Token name, Rc<Expr> iterable, Rc<Stmt> body",
+            "Function   : Token name, Rc<Vec<Token>> params, Rc<Vec<Option<Rc<Expr>>>> defaults, Option<Token> rest, Rc<Vec<Rc<Stmt>>> body, bool is_getter",
             "If         : Rc<Expr> condition, Rc<Stmt> then_branch, Option<Rc<Stmt>> else_branch",
-            "Print      : Rc<Expr> expression",
+            "Match      : Rc<Expr> subject, Rc<Vec<MatchArm>> arms",
+            "Print      : Token keyword, Rc<Expr> expression",
             "Return     : Token keyword, Option<Rc<Expr>> value",
-            "Var        : Token name, Option<Rc<Expr>> initializer",
+            "Trait      : Token name, Rc<Vec<Rc<Stmt>>> methods",
+            "Var        : Token name, Option<Rc<Expr>> initializer, bool is_const",
             "While      : Rc<Expr> condition, Rc<Stmt> body",
         ],
     )?;
@@ -61,6 +68,8 @@ fn define_ast(
     for i in imports {
         if i == &"rc" {
             writeln!(file, "use std::rc::Rc;")?;
+        } else if i == &"cell" {
+            writeln!(file, "use std::cell::Cell;")?;
         } else {
             writeln!(file, "use crate::{}::*;", i)?;
         }
@@ -103,22 +112,8 @@ fn define_ast(
     writeln!(file, "    }}")?;
     writeln!(file, "}}\n\nimpl Eq for {}{{}}\n", base_name)?;
 
-    writeln!(file, "use std::hash::{{Hash, Hasher}};")?;
-    writeln!(file, "impl Hash for {} {{", base_name)?;
-    writeln!(file, "    fn hash<H>(&self, hasher: &mut H)")?;
-    writeln!(file, "    where H: Hasher,")?;
-    writeln!(file, "    {{ match self {{ ")?;
-    for t in &tree_types {
-        writeln!(
-            file,
-            "        {}::{}(a) => {{ hasher.write_usize(Rc::as_ptr(a) as usize); }}",
-            base_name, t.base_class_name
-        )?;
-    }
-    writeln!(file, "        }}\n    }}\n}}\n")?;
-
     writeln!(file, "impl {} {{", base_name)?;
-    writeln!(file, "    pub fn accept<T>(&self, wrapper: Rc<{}>, {}_visitor: &dyn {base_name}Visitor<T>) -> Result<T, LoxResult> {{", base_name, base_name.to_lowercase())?;
+    writeln!(file, "    pub fn accept<T>(&self, wrapper: &Rc<{}>, {}_visitor: &dyn {base_name}Visitor<T>) -> Result<T, LoxResult> {{", base_name, base_name.to_lowercase())?;
     writeln!(file, "        match self {{")?;
     for t in &tree_types {
         writeln!(
@@ -146,7 +141,7 @@ fn define_ast(
     for t in &tree_types {
         writeln!(
             file,
-            "    fn visit_{0}_{1}(&self, wrapper: Rc<{3}>, {1}: &{2}) -> Result<T, LoxResult>;",
+            "    fn visit_{0}_{1}(&self, wrapper: &Rc<{3}>, {1}: &{2}) -> Result<T, LoxResult>;",
             t.base_class_name.to_lowercase(),
             base_name.to_lowercase(),
             t.class_name,
@@ -1,43 +1,283 @@
+use std::rc::Rc;
+use std::slice;
+
 use crate::error::*;
 use crate::expr::*;
+use crate::object::*;
+use crate::pattern::*;
+use crate::stmt::*;
 
+// Prints the tree as s-expressions, one statement per line, with the
+// statements a block, branch or loop contains indented below it
 pub struct AstPrinter;
 
 impl AstPrinter {
-    pub fn print(&self, expr: &Expr) -> Result<String, LoxError> {
-        expr.accept(self)
+    pub fn print(&self, statements: &[Rc<Stmt>]) -> Result<String, LoxResult> {
+        let mut lines = Vec::new();
+        for statement in statements {
+            lines.push(self.stmt(statement)?);
+        }
+        Ok(lines.join("\n"))
     }
 
-    fn parenthesize(&self, name: &String, exprs: &[&Box<Expr>]) -> Result<String, LoxError> {
-        let mut builder = format!("({name}");
+    fn stmt(&self, stmt: &Rc<Stmt>) -> Result<String, LoxResult> {
+        stmt.accept(stmt, self)
+    }
+
+    fn expr(&self, expr: &Rc<Expr>) -> Result<String, LoxResult> {
+        expr.accept(expr, self)
+    }
 
+    fn parenthesize(&self, name: &str, exprs: &[&Rc<Expr>]) -> Result<String, LoxResult> {
+        let mut builder = format!("({name}");
         for expr in exprs {
-            builder = format!("{builder} {}", expr.accept(self)?);
+            builder = format!("{builder} {}", self.expr(expr)?);
         }
-        builder = format!("{builder})");
+        Ok(format!("{builder})"))
+    }
 
-        Ok(builder)
+    // A header line with the given statements indented below it
+    fn nest(&self, head: String, statements: &[Rc<Stmt>]) -> Result<String, LoxResult> {
+        let mut builder = head;
+        for statement in statements {
+            for line in self.stmt(statement)?.lines() {
+                builder = format!("{builder}\n  {line}");
+            }
+        }
+        Ok(format!("{builder})"))
+    }
+
+    fn function(&self, keyword: &str, stmt: &FunctionStmt) -> Result<String, LoxResult> {
+        let mut params = Vec::new();
+        for (param, default) in stmt.params.iter().zip(stmt.defaults.iter()) {
+            params.push(match default {
+                Some(default) => format!("(= {} {})", param.as_string(), self.expr(default)?),
+                None => param.as_string(),
+            });
+        }
+        if let Some(rest) = &stmt.rest {
+            params.push(format!("...{}", rest.as_string()));
+        }
+        let head = format!(
+            "({keyword} {} ({})",
+            stmt.name.as_string(),
+            params.join(" ")
+        );
+        self.nest(head, &stmt.body)
+    }
+
+    fn methods(&self, keyword: &str, methods: &[Rc<Stmt>]) -> Result<Vec<String>, LoxResult> {
+        let mut printed = Vec::new();
+        for method in methods {
+            if let Stmt::Function(method) = &**method {
+                printed.push(self.function(keyword, method)?);
+            }
+        }
+        Ok(printed)
+    }
+}
+
+impl StmtVisitor<String> for AstPrinter {
+    fn visit_block_stmt(&self, _: &Rc<Stmt>, stmt: &BlockStmt) -> Result<String, LoxResult> {
+        self.nest("(block".to_string(), &stmt.statements)
+    }
+
+    fn visit_class_stmt(&self, _: &Rc<Stmt>, stmt: &ClassStmt) -> Result<String, LoxResult> {
+        let mut builder = format!("(class {}", stmt.name.as_string());
+        if let Some(superclass) = &stmt.superclass {
+            builder = format!("{builder} < {}", self.expr(superclass)?);
+        }
+        for t in stmt.traits.iter() {
+            builder = format!("{builder} with {}", self.expr(t)?);
+        }
+
+        let mut methods = self.methods("fun", &stmt.methods)?;
+        methods.extend(self.methods("class", &stmt.class_methods)?);
+        methods.extend(self.methods("set", &stmt.setters)?);
+        for method in methods {
+            for line in method.lines() {
+                builder = format!("{builder}\n  {line}");
+            }
+        }
+        Ok(format!("{builder})"))
+    }
+
+    fn visit_break_stmt(&self, _: &Rc<Stmt>, _: &BreakStmt) -> Result<String, LoxResult> {
+        Ok("(break)".to_string())
+    }
+
+    fn visit_do_stmt(&self, _: &Rc<Stmt>, stmt: &DoStmt) -> Result<String, LoxResult> {
+        let head = format!("(do-while {}", self.expr(&stmt.condition)?);
+        self.nest(head, slice::from_ref(&stmt.body))
+    }
+
+    fn visit_error_stmt(&self, _: &Rc<Stmt>, _: &ErrorStmt) -> Result<String, LoxResult> {
+        Ok("(error)".to_string())
+    }
+
+    fn visit_expression_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &ExpressionStmt,
+    ) -> Result<String, LoxResult> {
+        self.parenthesize(";", &[&stmt.expression])
+    }
+
+    fn visit_forin_stmt(&self, _: &Rc<Stmt>, stmt: &ForInStmt) -> Result<String, LoxResult> {
+        let head = format!(
+            "(for {} {}",
+            stmt.name.as_string(),
+            self.expr(&stmt.iterable)?
+        );
+        self.nest(head, slice::from_ref(&stmt.body))
+    }
+
+    fn visit_function_stmt(&self, _: &Rc<Stmt>, stmt: &FunctionStmt) -> Result<String, LoxResult> {
+        self.function("fun", stmt)
+    }
+
+    fn visit_if_stmt(&self, _: &Rc<Stmt>, stmt: &IfStmt) -> Result<String, LoxResult> {
+        let head = format!("(if {}", self.expr(&stmt.condition)?);
+        let mut branches = vec![stmt.then_branch.clone()];
+        branches.extend(stmt.else_branch.clone());
+        self.nest(head, &branches)
+    }
+
+    fn visit_match_stmt(&self, _: &Rc<Stmt>, stmt: &MatchStmt) -> Result<String, LoxResult> {
+        let mut builder = format!("(match {}", self.expr(&stmt.subject)?);
+        for arm in stmt.arms.iter() {
+            let patterns: Vec<String> = arm
+                .patterns
+                .iter()
+                .map(|pattern| match pattern {
+                    Pattern::Literal(value) => literal(value),
+                    Pattern::Wildcard => "_".to_string(),
+                })
+                .collect();
+            let case = self.nest(
+                format!("(case {}", patterns.join(" ")),
+                slice::from_ref(&arm.body),
+            )?;
+            for line in case.lines() {
+                builder = format!("{builder}\n  {line}");
+            }
+        }
+        Ok(format!("{builder})"))
+    }
+
+    fn visit_print_stmt(&self, _: &Rc<Stmt>, stmt: &PrintStmt) -> Result<String, LoxResult> {
+        self.parenthesize("print", &[&stmt.expression])
+    }
+
+    fn visit_return_stmt(&self, _: &Rc<Stmt>, stmt: &ReturnStmt) -> Result<String, LoxResult> {
+        match &stmt.value {
+            Some(value) => self.parenthesize("return", &[value]),
+            None => Ok("(return)".to_string()),
+        }
+    }
+
+    fn visit_trait_stmt(&self, _: &Rc<Stmt>, stmt: &TraitStmt) -> Result<String, LoxResult> {
+        let mut builder = format!("(trait {}", stmt.name.as_string());
+        for method in self.methods("fun", &stmt.methods)? {
+            for line in method.lines() {
+                builder = format!("{builder}\n  {line}");
+            }
+        }
+        Ok(format!("{builder})"))
+    }
+
+    fn visit_var_stmt(&self, _: &Rc<Stmt>, stmt: &VarStmt) -> Result<String, LoxResult> {
+        let keyword = if stmt.is_const { "const" } else { "var" };
+        let name = format!("{keyword} {}", stmt.name.as_string());
+        match &stmt.initializer {
+            Some(initializer) => self.parenthesize(&name, &[initializer]),
+            None => Ok(format!("({name})")),
+        }
+    }
+
+    fn visit_while_stmt(&self, _: &Rc<Stmt>, stmt: &WhileStmt) -> Result<String, LoxResult> {
+        let head = format!("(while {}", self.expr(&stmt.condition)?);
+        self.nest(head, slice::from_ref(&stmt.body))
     }
 }
 
 impl ExprVisitor<String> for AstPrinter {
-    fn visit_binary_expr(&self, expr: &BinaryExpr) -> Result<String, LoxError> {
-        self.parenthesize(&expr.operator.as_string(), &[&expr.left, &expr.right])
+    fn visit_assign_expr(&self, _: &Rc<Expr>, expr: &AssignExpr) -> Result<String, LoxResult> {
+        self.parenthesize(&format!("= {}", expr.name.as_string()), &[&expr.value])
     }
 
-    fn visit_grouping_expr(&self, expr: &GroupingExpr) -> Result<String, LoxError> {
-        self.parenthesize(&"group".to_string(), &[&expr.expression])
+    fn visit_binary_expr(&self, _: &Rc<Expr>, expr: &BinaryExpr) -> Result<String, LoxResult> {
+        self.parenthesize(&expr.operator.as_string(), &[&expr.left, &expr.right])
     }
 
-    fn visit_literal_expr(&self, expr: &LiteralExpr) -> Result<String, LoxError> {
-        if let Some(value) = &expr.value {
-            Ok(value.to_string())
-        } else {
-            Ok("nil".to_string())
+    fn visit_call_expr(&self, _: &Rc<Expr>, expr: &CallExpr) -> Result<String, LoxResult> {
+        let call = if expr.tail.get() { "tail-call" } else { "call" };
+        let mut builder = format!("({call} {}", self.expr(&expr.callee)?);
+        for (argument, name) in expr.arguments.iter().zip(expr.names.iter()) {
+            let argument = self.expr(argument)?;
+            builder = match name {
+                Some(name) => format!("{builder} {}: {argument}", name.as_string()),
+                None => format!("{builder} {argument}"),
+            };
         }
+        Ok(format!("{builder})"))
+    }
+
+    fn visit_error_expr(&self, _: &Rc<Expr>, _: &ErrorExpr) -> Result<String, LoxResult> {
+        Ok("(error)".to_string())
+    }
+
+    fn visit_get_expr(&self, _: &Rc<Expr>, expr: &GetExpr) -> Result<String, LoxResult> {
+        self.parenthesize(&format!(". {}", expr.name.as_string()), &[&expr.object])
     }
 
-    fn visit_unary_expr(&self, expr: &UnaryExpr) -> Result<String, LoxError> {
+    fn visit_grouping_expr(&self, _: &Rc<Expr>, expr: &GroupingExpr) -> Result<String, LoxResult> {
+        self.parenthesize("group", &[&expr.expression])
+    }
+
+    fn visit_index_expr(&self, _: &Rc<Expr>, expr: &IndexExpr) -> Result<String, LoxResult> {
+        self.parenthesize("[]", &[&expr.object, &expr.index])
+    }
+
+    fn visit_literal_expr(&self, _: &Rc<Expr>, expr: &LiteralExpr) -> Result<String, LoxResult> {
+        Ok(match &expr.value {
+            Some(value) => literal(value),
+            None => "nil".to_string(),
+        })
+    }
+
+    fn visit_logical_expr(&self, _: &Rc<Expr>, expr: &LogicalExpr) -> Result<String, LoxResult> {
+        self.parenthesize(&expr.operator.as_string(), &[&expr.left, &expr.right])
+    }
+
+    fn visit_set_expr(&self, _: &Rc<Expr>, expr: &SetExpr) -> Result<String, LoxResult> {
+        self.parenthesize(
+            &format!("= . {}", expr.name.as_string()),
+            &[&expr.object, &expr.value],
+        )
+    }
+
+    fn visit_super_expr(&self, _: &Rc<Expr>, expr: &SuperExpr) -> Result<String, LoxResult> {
+        Ok(format!("(super {})", expr.method.as_string()))
+    }
+
+    fn visit_this_expr(&self, _: &Rc<Expr>, _: &ThisExpr) -> Result<String, LoxResult> {
+        Ok("this".to_string())
+    }
+
+    fn visit_unary_expr(&self, _: &Rc<Expr>, expr: &UnaryExpr) -> Result<String, LoxResult> {
         self.parenthesize(&expr.operator.as_string(), &[&expr.right])
     }
+
+    fn visit_variable_expr(&self, _: &Rc<Expr>, expr: &VariableExpr) -> Result<String, LoxResult> {
+        Ok(expr.name.as_string())
+    }
+}
+
+// Strings are quoted so they can't be mistaken for names
+fn literal(value: &Object) -> String {
+    match value {
+        Object::Str(s) => format!("{s:?}"),
+        value => value.to_string(),
+    }
 }
@@ -1,5 +1,3 @@
-use core::fmt::{Debug, Display};
-
 use std::fmt;
 use std::rc::Rc;
 
@@ -7,39 +5,67 @@ use crate::error::*;
 use crate::interpreter::*;
 use crate::lox_class::*;
 use crate::object::*;
+use crate::token::*;
 
-#[derive(Clone)]
-pub struct Callable {
-    pub func: Rc<dyn LoxCallable>,
-}
+pub trait LoxCallable {
+    fn call(
+        &self,
+        interpreter: &Interpreter,
+        arguments: Vec<Object>,
+        klass: Option<Rc<LoxClass>>,
+    ) -> Result<Object, LoxResult>;
+    fn arity(&self) -> Arity;
 
-impl Debug for Callable {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "<Callable>")
+    // Calls with trailing `name: value` arguments. Only callables that know
+    // their parameter names can accept them.
+    fn call_named(
+        &self,
+        interpreter: &Interpreter,
+        arguments: Vec<Object>,
+        named: Vec<(Token, Object)>,
+        klass: Option<Rc<LoxClass>>,
+        _paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        if let Some((name, _)) = named.first() {
+            Err(LoxResult::runtime_error(
+                name,
+                &format!("Unknown argument '{}'.", name.as_string()),
+            ))
+        } else {
+            self.call(interpreter, arguments, klass)
+        }
     }
 }
 
-impl Display for Callable {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "<Callable>")
-    }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: usize,
+    pub variadic: bool,
 }
 
-impl PartialEq for Callable {
-    fn eq(&self, other: &Self) -> bool {
-        std::ptr::eq(
-            Rc::as_ptr(&self.func) as *const (),
-            Rc::as_ptr(&other.func) as *const (),
-        )
+impl Arity {
+    pub fn exact(n: usize) -> Arity {
+        Arity {
+            min: n,
+            max: n,
+            variadic: false,
+        }
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min && (self.variadic || count <= self.max)
     }
 }
 
-pub trait LoxCallable {
-    fn call(
-        &self,
-        interpreter: &Interpreter,
-        arguments: Vec<Object>,
-        klass: Option<Rc<LoxClass>>,
-    ) -> Result<Object, LoxResult>;
-    fn arity(&self) -> usize;
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.variadic {
+            write!(f, "at least {}", self.min)
+        } else if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{} to {}", self.min, self.max)
+        }
+    }
 }
@@ -0,0 +1,157 @@
+use std::rc::Rc;
+
+use crate::expr::*;
+use crate::inline_cache::*;
+use crate::object::*;
+use crate::symbol::*;
+use crate::token::*;
+
+// One VM instruction. Operands index into the chunk's tables: constants,
+// tokens (names, and operators to report errors at), functions, classes and
+// named-argument lists, and inline caches. Jumps are relative to the next
+// instruction.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u16),
+    SetLocal(u16),
+    GetUpvalue(u16),
+    SetUpvalue(u16),
+    GetGlobal(u32),
+    SetGlobal(u32),
+    DefineGlobal(u32),
+    DefineConstant(u32),
+    GetProperty(u32, u32),
+    SetProperty(u32, u32),
+    // Looks up a method to call right away. Pushes the unbound method and
+    // its receiver when it can skip binding, and the property and nil when
+    // it can't. Invoke then calls whichever it finds.
+    GetMethod(u32, u32),
+    GetSuper(u32),
+    Index(u32),
+    Equal(u32),
+    NotEqual(u32),
+    Greater(u32),
+    GreaterEqual(u32),
+    Less(u32),
+    LessEqual(u32),
+    Add(u32),
+    Subtract(u32),
+    Multiply(u32),
+    Divide(u32),
+    Is(u32),
+    Not,
+    Negate(u32),
+    // Pops a value and a match pattern and pushes whether they are equal
+    Same,
+    Print(u32),
+    Jump(u32),
+    JumpIfFalse(u32),
+    Loop(u32),
+    // Skips a parameter's default when the caller gave that argument
+    JumpIfProvided(u16, u32),
+    Call(u16, u32),
+    // A call in tail position, which reuses the caller's frame when it can.
+    // Otherwise it is an ordinary call and the Return after it runs.
+    TailCall(u16, u32),
+    CallNamed(u32, u32),
+    TailCallNamed(u32, u32),
+    Invoke(u16, u32),
+    Closure(u32),
+    CloseUpvalue,
+    Return,
+    Inherit(u32),
+    Class(u32),
+    Trait(u32),
+    IterStart(u32),
+    IterNext(u32, u32),
+    IterEnd,
+    // Code that failed to parse
+    Fail(u32),
+}
+
+// Where a closure finds a captured variable when it is created: a local slot
+// of the enclosing function, or one of that function's own upvalues
+#[derive(Debug, Clone, Copy)]
+pub struct UpvalueRef {
+    pub index: usize,
+    pub is_local: bool,
+}
+
+pub struct FunctionProto {
+    pub name: Token,
+    pub params: Rc<Vec<Token>>,
+    pub defaults: Rc<Vec<Option<Rc<Expr>>>>,
+    pub rest: Option<Token>,
+    pub is_getter: bool,
+    pub is_initializer: bool,
+    pub upvalues: Vec<UpvalueRef>,
+    pub chunk: Chunk,
+}
+
+// What the VM needs to build a class or trait from the closures the
+// compiler left on the stack
+pub struct ClassInfo {
+    pub name: Token,
+    pub has_superclass: bool,
+    pub traits: Vec<Token>,
+    pub methods: Vec<Symbol>,
+    pub class_methods: Vec<Symbol>,
+    pub setters: Vec<Symbol>,
+}
+
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<Object>,
+    pub tokens: Vec<Token>,
+    pub functions: Vec<Rc<FunctionProto>>,
+    pub classes: Vec<ClassInfo>,
+    pub names: Vec<Vec<Option<Token>>>,
+    pub caches: Vec<InlineCache>,
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Chunk::default()
+    }
+
+    pub fn write(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    pub fn add_constant(&mut self, value: Object) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn add_token(&mut self, token: &Token) -> usize {
+        self.tokens.push(token.dup());
+        self.tokens.len() - 1
+    }
+
+    pub fn add_function(&mut self, function: FunctionProto) -> usize {
+        self.functions.push(Rc::new(function));
+        self.functions.len() - 1
+    }
+
+    pub fn add_class(&mut self, class: ClassInfo) -> usize {
+        self.classes.push(class);
+        self.classes.len() - 1
+    }
+
+    pub fn add_names(&mut self, names: Vec<Option<Token>>) -> usize {
+        self.names.push(names);
+        self.names.len() - 1
+    }
+
+    pub fn add_cache(&mut self) -> usize {
+        self.caches.push(InlineCache::new());
+        self.caches.len() - 1
+    }
+}
@@ -0,0 +1,837 @@
+use std::cell::{Cell, RefCell};
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::chunk::*;
+use crate::error::*;
+use crate::expr::*;
+use crate::object::*;
+use crate::pattern::*;
+use crate::stmt::*;
+use crate::symbol::*;
+use crate::token::*;
+use crate::token_type::*;
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+    is_captured: bool,
+}
+
+// Jumps out of the innermost loop or match, which `break` leaves with only
+// the first `locals` locals still on the stack
+struct BreakTarget {
+    locals: usize,
+    jumps: Vec<usize>,
+}
+
+// The function being compiled. Slot 0 holds the callee, or `this` in methods.
+struct FunctionState {
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    breaks: Vec<BreakTarget>,
+    is_initializer: bool,
+}
+
+impl FunctionState {
+    fn new(slot_zero: Symbol, is_initializer: bool) -> FunctionState {
+        FunctionState {
+            chunk: Chunk::new(),
+            locals: vec![Local {
+                name: slot_zero,
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            breaks: Vec::new(),
+            is_initializer,
+        }
+    }
+
+    fn resolve_local(&self, name: Symbol) -> Option<usize> {
+        self.locals.iter().rposition(|local| local.name == name)
+    }
+
+    fn add_upvalue(&mut self, index: usize, is_local: bool) -> usize {
+        if let Some(existing) = self
+            .upvalues
+            .iter()
+            .position(|u| u.index == index && u.is_local == is_local)
+        {
+            return existing;
+        }
+        self.upvalues.push(UpvalueRef { index, is_local });
+        self.upvalues.len() - 1
+    }
+}
+
+// Looks `name` up in the functions enclosing `functions[level]`, capturing
+// it into each function on the way down
+fn resolve_upvalue(functions: &mut [FunctionState], level: usize, name: Symbol) -> Option<usize> {
+    if level == 0 {
+        return None;
+    }
+
+    if let Some(local) = functions[level - 1].resolve_local(name) {
+        functions[level - 1].locals[local].is_captured = true;
+        return Some(functions[level].add_upvalue(local, true));
+    }
+
+    resolve_upvalue(functions, level - 1, name)
+        .map(|upvalue| functions[level].add_upvalue(upvalue, false))
+}
+
+enum Variable {
+    Local(u16),
+    Upvalue(u16),
+    Global(u32),
+}
+
+// Compiles the resolved tree into bytecode for the VM. Locals get stack slots
+// and captured variables become upvalues; anything not found in an enclosing
+// scope is a global, just as the resolver decided.
+pub struct Compiler {
+    functions: RefCell<Vec<FunctionState>>,
+    line: Cell<usize>,
+}
+
+impl StmtVisitor<()> for Compiler {
+    fn visit_block_stmt(&self, _: &Rc<Stmt>, stmt: &BlockStmt) -> Result<(), LoxResult> {
+        self.begin_scope();
+        for statement in stmt.statements.iter() {
+            self.statement(statement)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_class_stmt(&self, _: &Rc<Stmt>, stmt: &ClassStmt) -> Result<(), LoxResult> {
+        let slot = self.declare_placeholder(&stmt.name)?;
+
+        if let Some(superclass) = &stmt.superclass {
+            self.expression(superclass)?;
+            let token = self.token(&variable_name(superclass))?;
+            self.emit(Op::Inherit(token));
+            self.begin_scope();
+            self.add_local(Symbol::SUPER);
+        }
+
+        for t in stmt.traits.iter() {
+            self.expression(t)?;
+        }
+        for method in stmt.methods.iter() {
+            self.function(function_stmt(method), true, true)?;
+        }
+        for method in stmt.class_methods.iter().chain(stmt.setters.iter()) {
+            self.function(function_stmt(method), true, false)?;
+        }
+
+        let info = ClassInfo {
+            name: stmt.name.dup(),
+            has_superclass: stmt.superclass.is_some(),
+            traits: stmt.traits.iter().map(|t| variable_name(t)).collect(),
+            methods: method_names(&stmt.methods),
+            class_methods: method_names(&stmt.class_methods),
+            setters: method_names(&stmt.setters),
+        };
+        let info = self.operand(self.chunk(|c| c.add_class(info)))?;
+        self.emit(Op::Class(info));
+
+        self.define_placeholder(&stmt.name, slot)?;
+
+        if stmt.superclass.is_some() {
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    fn visit_break_stmt(&self, _: &Rc<Stmt>, stmt: &BreakStmt) -> Result<(), LoxResult> {
+        self.line.set(stmt.token.line);
+        let locals = self.break_locals();
+        for is_captured in locals {
+            self.emit(if is_captured {
+                Op::CloseUpvalue
+            } else {
+                Op::Pop
+            });
+        }
+        let jump = self.emit(Op::Jump(0));
+        let mut functions = self.functions.borrow_mut();
+        let state = functions.last_mut().unwrap();
+        state.breaks.last_mut().unwrap().jumps.push(jump);
+        Ok(())
+    }
+
+    fn visit_do_stmt(&self, _: &Rc<Stmt>, stmt: &DoStmt) -> Result<(), LoxResult> {
+        let start = self.code_len();
+        self.begin_breakable();
+        self.statement(&stmt.body)?;
+        self.expression(&stmt.condition)?;
+        let exit = self.emit(Op::JumpIfFalse(0));
+        self.emit(Op::Pop);
+        self.emit_loop(start)?;
+        self.patch_jump(exit)?;
+        self.emit(Op::Pop);
+        self.end_breakable()
+    }
+
+    fn visit_error_stmt(&self, _: &Rc<Stmt>, stmt: &ErrorStmt) -> Result<(), LoxResult> {
+        let token = self.token(&stmt.tokens[0])?;
+        self.emit(Op::Fail(token));
+        Ok(())
+    }
+
+    fn visit_expression_stmt(&self, _: &Rc<Stmt>, stmt: &ExpressionStmt) -> Result<(), LoxResult> {
+        self.expression(&stmt.expression)?;
+        self.emit(Op::Pop);
+        Ok(())
+    }
+
+    fn visit_forin_stmt(&self, _: &Rc<Stmt>, stmt: &ForInStmt) -> Result<(), LoxResult> {
+        self.line.set(stmt.name.line);
+        self.expression(&stmt.iterable)?;
+        let name = self.token(&stmt.name)?;
+        self.emit(Op::IterStart(name));
+        self.begin_breakable();
+
+        let start = self.code_len();
+        let exit = self.emit(Op::IterNext(name, 0));
+        self.begin_scope();
+        self.add_local(stmt.name.lexeme());
+        self.statement(&stmt.body)?;
+        self.end_scope();
+        self.emit_loop(start)?;
+        self.patch_jump(exit)?;
+
+        self.end_breakable()?;
+        self.emit(Op::IterEnd);
+        Ok(())
+    }
+
+    fn visit_function_stmt(&self, _: &Rc<Stmt>, stmt: &FunctionStmt) -> Result<(), LoxResult> {
+        self.line.set(stmt.name.line);
+        if self.scope_depth() > 0 {
+            self.add_local(stmt.name.lexeme());
+            self.function(stmt, false, false)
+        } else {
+            let name = self.token(&stmt.name)?;
+            self.function(stmt, false, false)?;
+            self.emit(Op::DefineGlobal(name));
+            Ok(())
+        }
+    }
+
+    fn visit_if_stmt(&self, _: &Rc<Stmt>, stmt: &IfStmt) -> Result<(), LoxResult> {
+        self.expression(&stmt.condition)?;
+        let then_jump = self.emit(Op::JumpIfFalse(0));
+        self.emit(Op::Pop);
+        self.statement(&stmt.then_branch)?;
+        let else_jump = self.emit(Op::Jump(0));
+        self.patch_jump(then_jump)?;
+        self.emit(Op::Pop);
+        if let Some(else_branch) = &stmt.else_branch {
+            self.statement(else_branch)?;
+        }
+        self.patch_jump(else_jump)
+    }
+
+    fn visit_match_stmt(&self, _: &Rc<Stmt>, stmt: &MatchStmt) -> Result<(), LoxResult> {
+        self.expression(&stmt.subject)?;
+        self.begin_scope();
+        self.add_local(Symbol::intern(""));
+        let subject = self.slot(self.functions.borrow().last().unwrap().locals.len() - 1)?;
+
+        let mut ends = Vec::new();
+        for arm in stmt.arms.iter() {
+            self.line.set(arm.token.line);
+            let mut bodies = Vec::new();
+            for pattern in arm.patterns.iter() {
+                match pattern {
+                    Pattern::Wildcard => bodies.push(self.emit(Op::Jump(0))),
+                    Pattern::Literal(value) => {
+                        self.emit(Op::GetLocal(subject));
+                        self.constant(value.clone())?;
+                        self.emit(Op::Same);
+                        let next = self.emit(Op::JumpIfFalse(0));
+                        self.emit(Op::Pop);
+                        bodies.push(self.emit(Op::Jump(0)));
+                        self.patch_jump(next)?;
+                        self.emit(Op::Pop);
+                    }
+                }
+            }
+            let next_arm = self.emit(Op::Jump(0));
+
+            for body in bodies {
+                self.patch_jump(body)?;
+            }
+            self.statement(&arm.body)?;
+            ends.push(self.emit(Op::Jump(0)));
+            self.patch_jump(next_arm)?;
+        }
+
+        for end in ends {
+            self.patch_jump(end)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_print_stmt(&self, _: &Rc<Stmt>, stmt: &PrintStmt) -> Result<(), LoxResult> {
+        self.line.set(stmt.keyword.line);
+        self.expression(&stmt.expression)?;
+        let keyword = self.token(&stmt.keyword)?;
+        self.emit(Op::Print(keyword));
+        Ok(())
+    }
+
+    fn visit_return_stmt(&self, _: &Rc<Stmt>, stmt: &ReturnStmt) -> Result<(), LoxResult> {
+        self.line.set(stmt.keyword.line);
+        if let Some(value) = &stmt.value {
+            self.expression(value)?;
+            self.emit(Op::Return);
+        } else {
+            self.emit_return();
+        }
+        Ok(())
+    }
+
+    fn visit_trait_stmt(&self, _: &Rc<Stmt>, stmt: &TraitStmt) -> Result<(), LoxResult> {
+        let slot = self.declare_placeholder(&stmt.name)?;
+
+        for method in stmt.methods.iter() {
+            self.function(function_stmt(method), true, true)?;
+        }
+        let info = ClassInfo {
+            name: stmt.name.dup(),
+            has_superclass: false,
+            traits: Vec::new(),
+            methods: method_names(&stmt.methods),
+            class_methods: Vec::new(),
+            setters: Vec::new(),
+        };
+        let info = self.operand(self.chunk(|c| c.add_class(info)))?;
+        self.emit(Op::Trait(info));
+        self.define_placeholder(&stmt.name, slot)
+    }
+
+    fn visit_var_stmt(&self, _: &Rc<Stmt>, stmt: &VarStmt) -> Result<(), LoxResult> {
+        self.line.set(stmt.name.line);
+        if let Some(initializer) = &stmt.initializer {
+            self.expression(initializer)?;
+        } else {
+            self.emit(Op::Nil);
+        }
+
+        if self.scope_depth() > 0 {
+            self.add_local(stmt.name.lexeme());
+        } else {
+            let name = self.token(&stmt.name)?;
+            self.emit(if stmt.is_const {
+                Op::DefineConstant(name)
+            } else {
+                Op::DefineGlobal(name)
+            });
+        }
+        Ok(())
+    }
+
+    fn visit_while_stmt(&self, _: &Rc<Stmt>, stmt: &WhileStmt) -> Result<(), LoxResult> {
+        let start = self.code_len();
+        self.expression(&stmt.condition)?;
+        let exit = self.emit(Op::JumpIfFalse(0));
+        self.emit(Op::Pop);
+        self.begin_breakable();
+        self.statement(&stmt.body)?;
+        self.emit_loop(start)?;
+        self.patch_jump(exit)?;
+        self.emit(Op::Pop);
+        self.end_breakable()
+    }
+}
+
+impl ExprVisitor<()> for Compiler {
+    fn visit_assign_expr(&self, _: &Rc<Expr>, expr: &AssignExpr) -> Result<(), LoxResult> {
+        self.expression(&expr.value)?;
+        let op = match self.variable(&expr.name)? {
+            Variable::Local(slot) => Op::SetLocal(slot),
+            Variable::Upvalue(index) => Op::SetUpvalue(index),
+            Variable::Global(name) => Op::SetGlobal(name),
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    fn visit_binary_expr(&self, _: &Rc<Expr>, expr: &BinaryExpr) -> Result<(), LoxResult> {
+        self.expression(&expr.left)?;
+        self.expression(&expr.right)?;
+        let operator = self.token(&expr.operator)?;
+        let op = match expr.operator.token_type() {
+            TokenType::Equals => Op::Equal(operator),
+            TokenType::BangEqual => Op::NotEqual(operator),
+            TokenType::Greater => Op::Greater(operator),
+            TokenType::GreaterEqual => Op::GreaterEqual(operator),
+            TokenType::Less => Op::Less(operator),
+            TokenType::LessEqual => Op::LessEqual(operator),
+            TokenType::Plus => Op::Add(operator),
+            TokenType::Minus => Op::Subtract(operator),
+            TokenType::Star => Op::Multiply(operator),
+            TokenType::Slash => Op::Divide(operator),
+            TokenType::Is => Op::Is(operator),
+            _ => panic!("unknown binary operator {:?}", expr.operator),
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    fn visit_call_expr(&self, _: &Rc<Expr>, expr: &CallExpr) -> Result<(), LoxResult> {
+        let positional = expr.names.iter().all(Option::is_none);
+        match expr.callee.deref() {
+            Expr::Get(get) if positional && !expr.tail.get() => {
+                self.expression(&get.object)?;
+                let name = self.token(&get.name)?;
+                let cache = self.cache()?;
+                self.emit(Op::GetMethod(name, cache));
+                for argument in expr.arguments.iter() {
+                    self.expression(argument)?;
+                }
+                let paren = self.token(&expr.paren)?;
+                self.emit(Op::Invoke(expr.arguments.len() as u16, paren));
+                return Ok(());
+            }
+            _ => self.expression(&expr.callee)?,
+        }
+        for argument in expr.arguments.iter() {
+            self.expression(argument)?;
+        }
+        let paren = self.token(&expr.paren)?;
+        if positional {
+            let argc = expr.arguments.len() as u16;
+            self.emit(if expr.tail.get() {
+                Op::TailCall(argc, paren)
+            } else {
+                Op::Call(argc, paren)
+            });
+        } else {
+            let names = expr
+                .names
+                .iter()
+                .map(|name| name.as_ref().map(Token::dup))
+                .collect();
+            let names = self.operand(self.chunk(|c| c.add_names(names)))?;
+            self.emit(if expr.tail.get() {
+                Op::TailCallNamed(names, paren)
+            } else {
+                Op::CallNamed(names, paren)
+            });
+        }
+        Ok(())
+    }
+
+    fn visit_error_expr(&self, _: &Rc<Expr>, expr: &ErrorExpr) -> Result<(), LoxResult> {
+        let token = self.token(&expr.token)?;
+        self.emit(Op::Fail(token));
+        Ok(())
+    }
+
+    fn visit_get_expr(&self, _: &Rc<Expr>, expr: &GetExpr) -> Result<(), LoxResult> {
+        self.expression(&expr.object)?;
+        let name = self.token(&expr.name)?;
+        let cache = self.cache()?;
+        self.emit(Op::GetProperty(name, cache));
+        Ok(())
+    }
+
+    fn visit_grouping_expr(&self, _: &Rc<Expr>, expr: &GroupingExpr) -> Result<(), LoxResult> {
+        self.expression(&expr.expression)
+    }
+
+    fn visit_index_expr(&self, _: &Rc<Expr>, expr: &IndexExpr) -> Result<(), LoxResult> {
+        self.expression(&expr.object)?;
+        self.expression(&expr.index)?;
+        let bracket = self.token(&expr.bracket)?;
+        self.emit(Op::Index(bracket));
+        Ok(())
+    }
+
+    fn visit_literal_expr(&self, _: &Rc<Expr>, expr: &LiteralExpr) -> Result<(), LoxResult> {
+        match expr.value.clone().unwrap() {
+            Object::Nil => self.emit(Op::Nil),
+            Object::Bool(true) => self.emit(Op::True),
+            Object::Bool(false) => self.emit(Op::False),
+            value => return self.constant(value),
+        };
+        Ok(())
+    }
+
+    fn visit_logical_expr(&self, _: &Rc<Expr>, expr: &LogicalExpr) -> Result<(), LoxResult> {
+        self.expression(&expr.left)?;
+        if expr.operator.is(TokenType::Or) {
+            let else_jump = self.emit(Op::JumpIfFalse(0));
+            let end_jump = self.emit(Op::Jump(0));
+            self.patch_jump(else_jump)?;
+            self.emit(Op::Pop);
+            self.expression(&expr.right)?;
+            self.patch_jump(end_jump)
+        } else {
+            let end_jump = self.emit(Op::JumpIfFalse(0));
+            self.emit(Op::Pop);
+            self.expression(&expr.right)?;
+            self.patch_jump(end_jump)
+        }
+    }
+
+    fn visit_set_expr(&self, _: &Rc<Expr>, expr: &SetExpr) -> Result<(), LoxResult> {
+        self.expression(&expr.object)?;
+        self.expression(&expr.value)?;
+        let name = self.token(&expr.name)?;
+        let cache = self.cache()?;
+        self.emit(Op::SetProperty(name, cache));
+        Ok(())
+    }
+
+    fn visit_super_expr(&self, _: &Rc<Expr>, expr: &SuperExpr) -> Result<(), LoxResult> {
+        self.get_variable(&expr.keyword, Symbol::SUPER)?;
+        self.get_variable(&expr.keyword, Symbol::THIS)?;
+        let method = self.token(&expr.method)?;
+        self.emit(Op::GetSuper(method));
+        Ok(())
+    }
+
+    fn visit_this_expr(&self, _: &Rc<Expr>, expr: &ThisExpr) -> Result<(), LoxResult> {
+        self.get_variable(&expr.keyword, Symbol::THIS)
+    }
+
+    fn visit_unary_expr(&self, _: &Rc<Expr>, expr: &UnaryExpr) -> Result<(), LoxResult> {
+        self.expression(&expr.right)?;
+        if expr.operator.is(TokenType::Bang) {
+            self.emit(Op::Not);
+        } else {
+            let operator = self.token(&expr.operator)?;
+            self.emit(Op::Negate(operator));
+        }
+        Ok(())
+    }
+
+    fn visit_variable_expr(&self, _: &Rc<Expr>, expr: &VariableExpr) -> Result<(), LoxResult> {
+        self.get_variable(&expr.name, expr.name.lexeme())
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler {
+            functions: RefCell::new(Vec::new()),
+            line: Cell::new(1),
+        }
+    }
+
+    // Compiles a script into a function taking no arguments
+    pub fn compile(&self, statements: &[Rc<Stmt>]) -> Result<Rc<FunctionProto>, LoxResult> {
+        self.functions
+            .replace(vec![FunctionState::new(Symbol::intern(""), false)]);
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.emit_return();
+
+        let state = self.functions.borrow_mut().pop().unwrap();
+        Ok(Rc::new(FunctionProto {
+            name: Token::new(TokenType::Identifier, "script", None, 0),
+            params: Rc::new(Vec::new()),
+            defaults: Rc::new(Vec::new()),
+            rest: None,
+            is_getter: false,
+            is_initializer: false,
+            upvalues: state.upvalues,
+            chunk: state.chunk,
+        }))
+    }
+
+    fn statement(&self, stmt: &Rc<Stmt>) -> Result<(), LoxResult> {
+        stmt.accept(stmt, self)
+    }
+
+    fn expression(&self, expr: &Rc<Expr>) -> Result<(), LoxResult> {
+        expr.accept(expr, self)
+    }
+
+    // Compiles a function body and emits the closure that captures its
+    // upvalues. Each default is compiled with only the earlier parameters in
+    // scope, and only runs when the caller left that argument out.
+    fn function(
+        &self,
+        stmt: &FunctionStmt,
+        is_method: bool,
+        allow_init: bool,
+    ) -> Result<(), LoxResult> {
+        let is_initializer = allow_init && stmt.name.lexeme() == Symbol::INIT;
+        let slot_zero = if is_method {
+            Symbol::THIS
+        } else {
+            Symbol::intern("")
+        };
+        self.functions
+            .borrow_mut()
+            .push(FunctionState::new(slot_zero, is_initializer));
+        self.begin_scope();
+
+        for (i, (param, default)) in stmt.params.iter().zip(stmt.defaults.iter()).enumerate() {
+            if let Some(default) = default {
+                let skip = self.emit(Op::JumpIfProvided(i as u16, 0));
+                self.expression(default)?;
+                self.emit(Op::SetLocal(i as u16 + 1));
+                self.emit(Op::Pop);
+                self.patch_jump(skip)?;
+            }
+            self.add_local(param.lexeme());
+        }
+        if let Some(rest) = &stmt.rest {
+            self.add_local(rest.lexeme());
+        }
+
+        for statement in stmt.body.iter() {
+            self.statement(statement)?;
+        }
+        self.emit_return();
+
+        let state = self.functions.borrow_mut().pop().unwrap();
+        let function = FunctionProto {
+            name: stmt.name.dup(),
+            params: Rc::clone(&stmt.params),
+            defaults: Rc::clone(&stmt.defaults),
+            rest: stmt.rest.as_ref().map(Token::dup),
+            is_getter: stmt.is_getter,
+            is_initializer,
+            upvalues: state.upvalues,
+            chunk: state.chunk,
+        };
+        let function = self.operand(self.chunk(|c| c.add_function(function)))?;
+        self.emit(Op::Closure(function));
+        Ok(())
+    }
+
+    fn variable(&self, token: &Token) -> Result<Variable, LoxResult> {
+        self.resolve(token, token.lexeme())
+    }
+
+    fn resolve(&self, token: &Token, name: Symbol) -> Result<Variable, LoxResult> {
+        self.line.set(token.line);
+        {
+            let mut functions = self.functions.borrow_mut();
+            let level = functions.len() - 1;
+            if let Some(slot) = functions[level].resolve_local(name) {
+                return Ok(Variable::Local(self.slot(slot)?));
+            }
+            if let Some(index) = resolve_upvalue(&mut functions, level, name) {
+                return u16::try_from(index).map(Variable::Upvalue).map_err(|_| {
+                    LoxResult::error(self.line.get(), "Too many closure variables in function.")
+                });
+            }
+        }
+        Ok(Variable::Global(self.token(token)?))
+    }
+
+    fn get_variable(&self, token: &Token, name: Symbol) -> Result<(), LoxResult> {
+        let op = match self.resolve(token, name)? {
+            Variable::Local(slot) => Op::GetLocal(slot),
+            Variable::Upvalue(index) => Op::GetUpvalue(index),
+            Variable::Global(name) => Op::GetGlobal(name),
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    fn scope_depth(&self) -> usize {
+        self.functions.borrow().last().unwrap().scope_depth
+    }
+
+    fn begin_scope(&self) {
+        self.functions.borrow_mut().last_mut().unwrap().scope_depth += 1;
+    }
+
+    fn end_scope(&self) {
+        let mut functions = self.functions.borrow_mut();
+        let state = functions.last_mut().unwrap();
+        state.scope_depth -= 1;
+        while let Some(local) = state.locals.last() {
+            if local.depth <= state.scope_depth {
+                break;
+            }
+            let op = if local.is_captured {
+                Op::CloseUpvalue
+            } else {
+                Op::Pop
+            };
+            state.chunk.write(op);
+            state.locals.pop();
+        }
+    }
+
+    // A local class or trait gets its slot before its methods are compiled so
+    // they can refer to it. The slot holds nil until the value is built.
+    fn declare_placeholder(&self, name: &Token) -> Result<Option<u16>, LoxResult> {
+        self.line.set(name.line);
+        if self.scope_depth() == 0 {
+            return Ok(None);
+        }
+        self.emit(Op::Nil);
+        self.add_local(name.lexeme());
+        let slot = self.slot(self.functions.borrow().last().unwrap().locals.len() - 1)?;
+        Ok(Some(slot))
+    }
+
+    fn define_placeholder(&self, name: &Token, slot: Option<u16>) -> Result<(), LoxResult> {
+        if let Some(slot) = slot {
+            self.emit(Op::SetLocal(slot));
+            self.emit(Op::Pop);
+        } else {
+            let name = self.token(name)?;
+            self.emit(Op::DefineGlobal(name));
+        }
+        Ok(())
+    }
+
+    fn add_local(&self, name: Symbol) {
+        let mut functions = self.functions.borrow_mut();
+        let state = functions.last_mut().unwrap();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name,
+            depth,
+            is_captured: false,
+        });
+    }
+
+    fn begin_breakable(&self) {
+        let mut functions = self.functions.borrow_mut();
+        let state = functions.last_mut().unwrap();
+        let locals = state.locals.len();
+        state.breaks.push(BreakTarget {
+            locals,
+            jumps: Vec::new(),
+        });
+    }
+
+    fn end_breakable(&self) -> Result<(), LoxResult> {
+        let target = {
+            let mut functions = self.functions.borrow_mut();
+            functions.last_mut().unwrap().breaks.pop().unwrap()
+        };
+        for jump in target.jumps {
+            self.patch_jump(jump)?;
+        }
+        Ok(())
+    }
+
+    // Whether each local that `break` leaves behind was captured, innermost
+    // first
+    fn break_locals(&self) -> Vec<bool> {
+        let functions = self.functions.borrow();
+        let state = functions.last().unwrap();
+        let target = state.breaks.last().unwrap();
+        state.locals[target.locals..]
+            .iter()
+            .rev()
+            .map(|local| local.is_captured)
+            .collect()
+    }
+
+    fn chunk<T>(&self, f: impl FnOnce(&mut Chunk) -> T) -> T {
+        f(&mut self.functions.borrow_mut().last_mut().unwrap().chunk)
+    }
+
+    fn code_len(&self) -> usize {
+        self.chunk(|c| c.code.len())
+    }
+
+    fn emit(&self, op: Op) -> usize {
+        self.chunk(|c| c.write(op))
+    }
+
+    fn emit_return(&self) {
+        let is_initializer = self.functions.borrow().last().unwrap().is_initializer;
+        if is_initializer {
+            self.emit(Op::GetLocal(0));
+        } else {
+            self.emit(Op::Nil);
+        }
+        self.emit(Op::Return);
+    }
+
+    fn emit_loop(&self, start: usize) -> Result<(), LoxResult> {
+        let offset = self.jump_offset(self.code_len() + 1 - start)?;
+        self.emit(Op::Loop(offset));
+        Ok(())
+    }
+
+    fn patch_jump(&self, jump: usize) -> Result<(), LoxResult> {
+        let offset = self.jump_offset(self.code_len() - jump - 1)?;
+        self.chunk(|c| {
+            c.code[jump] = match c.code[jump] {
+                Op::Jump(_) => Op::Jump(offset),
+                Op::JumpIfFalse(_) => Op::JumpIfFalse(offset),
+                Op::JumpIfProvided(param, _) => Op::JumpIfProvided(param, offset),
+                Op::IterNext(name, _) => Op::IterNext(name, offset),
+                op => panic!("tried to patch {op:?}"),
+            }
+        });
+        Ok(())
+    }
+
+    fn jump_offset(&self, offset: usize) -> Result<u32, LoxResult> {
+        u32::try_from(offset)
+            .map_err(|_| LoxResult::error(self.line.get(), "Too much code to jump over."))
+    }
+
+    fn constant(&self, value: Object) -> Result<(), LoxResult> {
+        let index = self.operand(self.chunk(|c| c.add_constant(value)))?;
+        self.emit(Op::Constant(index));
+        Ok(())
+    }
+
+    fn token(&self, token: &Token) -> Result<u32, LoxResult> {
+        self.line.set(token.line);
+        self.operand(self.chunk(|c| c.add_token(token)))
+    }
+
+    fn cache(&self) -> Result<u32, LoxResult> {
+        self.operand(self.chunk(|c| c.add_cache()))
+    }
+
+    fn slot(&self, index: usize) -> Result<u16, LoxResult> {
+        u16::try_from(index)
+            .map_err(|_| LoxResult::error(self.line.get(), "Too many local variables in function."))
+    }
+
+    fn operand(&self, index: usize) -> Result<u32, LoxResult> {
+        u32::try_from(index)
+            .map_err(|_| LoxResult::error(self.line.get(), "Too many constants in one chunk."))
+    }
+}
+
+fn function_stmt(stmt: &Rc<Stmt>) -> &FunctionStmt {
+    if let Stmt::Function(function) = stmt.deref() {
+        function
+    } else {
+        panic!("non-function method in class");
+    }
+}
+
+fn method_names(methods: &[Rc<Stmt>]) -> Vec<Symbol> {
+    methods
+        .iter()
+        .map(|m| function_stmt(m).name.lexeme())
+        .collect()
+}
+
+fn variable_name(expr: &Expr) -> Token {
+    if let Expr::Variable(v) = expr {
+        v.name.dup()
+    } else {
+        panic!("could not extract variable expr");
+    }
+}
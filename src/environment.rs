@@ -1,15 +1,29 @@
 use std::cell::RefCell;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 use crate::error::*;
+use crate::gc::*;
 use crate::object::*;
+use crate::symbol::*;
 use crate::token::*;
 
+// Where the resolver found a local: how many scopes out it lives, and its
+// position among that scope's declarations
+#[derive(Debug, Clone, Copy)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+// Globals are looked up by name since they can be defined after the code that
+// uses them is resolved. Locals live in `slots`, in declaration order.
 #[derive(Debug)]
 pub struct Environment {
-    values: HashMap<String, Object>,
+    values: HashMap<Symbol, Object>,
+    slots: Vec<Object>,
+    constants: HashSet<Symbol>,
     enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
@@ -17,6 +31,8 @@ impl Environment {
     pub fn new() -> Environment {
         Environment {
             values: HashMap::new(),
+            slots: Vec::new(),
+            constants: HashSet::new(),
             enclosing: None,
         }
     }
@@ -24,46 +40,57 @@ impl Environment {
     pub fn new_with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Environment {
         Environment {
             values: HashMap::new(),
+            slots: Vec::new(),
+            constants: HashSet::new(),
             enclosing: Some(enclosing),
         }
     }
 
-    pub fn define(&mut self, name: &str, value: Object) {
-        self.values.insert(name.to_string(), value);
+    pub fn define(&mut self, name: Symbol, value: Object) {
+        if self.enclosing.is_some() {
+            self.slots.push(value);
+        } else {
+            self.constants.remove(&name);
+            self.values.insert(name, value);
+        }
+    }
+
+    // Local constants are checked by the resolver, so only globals need to
+    // remember which names are constant
+    pub fn define_constant(&mut self, name: Symbol, value: Object) {
+        self.define(name, value);
+        if self.enclosing.is_none() {
+            self.constants.insert(name);
+        }
     }
 
-    pub fn get_at(&self, distance: usize, name: &str) -> Result<Object, LoxResult> {
-        if distance == 0 {
-            Ok(self.values.get(name).unwrap().clone())
+    pub fn get_at(&self, slot: Slot) -> Object {
+        if slot.depth == 0 {
+            self.slots[slot.index].clone()
         } else {
-            self.enclosing
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_at(distance - 1, name)
+            self.enclosing.as_ref().unwrap().borrow().get_at(Slot {
+                depth: slot.depth - 1,
+                index: slot.index,
+            })
         }
     }
 
-    pub fn assign_at(
-        &mut self,
-        distance: usize,
-        name: &Token,
-        value: Object,
-    ) -> Result<(), LoxResult> {
-        if distance == 0 {
-            self.values.insert(name.as_string(), value);
-            Ok(())
+    pub fn assign_at(&mut self, slot: Slot, value: Object) {
+        if slot.depth == 0 {
+            self.slots[slot.index] = value;
         } else {
-            self.enclosing
-                .as_ref()
-                .unwrap()
-                .borrow_mut()
-                .assign_at(distance - 1, name, value)
+            self.enclosing.as_ref().unwrap().borrow_mut().assign_at(
+                Slot {
+                    depth: slot.depth - 1,
+                    index: slot.index,
+                },
+                value,
+            )
         }
     }
 
     pub fn get(&self, name: &Token) -> Result<Object, LoxResult> {
-        if let Some(object) = self.values.get(&name.as_string()) {
+        if let Some(object) = self.values.get(&name.lexeme()) {
             Ok(object.clone())
         } else if let Some(enclosing) = &self.enclosing {
             enclosing.borrow().get(name)
@@ -76,7 +103,13 @@ impl Environment {
     }
 
     pub fn assign(&mut self, name: &Token, value: Object) -> Result<(), LoxResult> {
-        if let Entry::Occupied(mut object) = self.values.entry(name.as_string()) {
+        if let Entry::Occupied(mut object) = self.values.entry(name.lexeme()) {
+            if self.constants.contains(object.key()) {
+                return Err(LoxResult::runtime_error(
+                    name,
+                    &format!("Can't assign to constant '{}'.", name.as_string()),
+                ));
+            }
             object.insert(value);
             Ok(())
         } else if let Some(enclosing) = &self.enclosing {
@@ -88,6 +121,23 @@ impl Environment {
             ))
         }
     }
+
+    // Drops everything the environment holds, for the cycle collector
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.slots.clear();
+        self.enclosing = None;
+    }
+}
+
+impl Trace for Environment {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        edges.extend(self.values.values().filter_map(Node::of));
+        edges.extend(self.slots.iter().filter_map(Node::of));
+        if let Some(enclosing) = &self.enclosing {
+            edges.push(Node::Environment(Rc::clone(enclosing)));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -99,11 +149,11 @@ mod tests {
     fn can_define_a_variable() {
         let mut e = Environment::new();
 
-        e.define(&"One".to_string(), Object::Bool(true));
+        e.define(Symbol::intern("One"), Object::Bool(true));
 
-        assert!(e.values.contains_key(&"One".to_string()));
+        assert!(e.values.contains_key(&Symbol::intern("One")));
         assert_eq!(
-            e.values.get(&"One".to_string()).unwrap(),
+            e.values.get(&Symbol::intern("One")).unwrap(),
             &Object::Bool(true)
         );
     }
@@ -111,10 +161,10 @@ mod tests {
     #[test]
     fn can_redefine_a_variable() {
         let mut e = Environment::new();
-        e.define(&"Two".to_string(), Object::Bool(true));
-        e.define(&"Two".to_string(), Object::Num(12.0));
+        e.define(Symbol::intern("Two"), Object::Bool(true));
+        e.define(Symbol::intern("Two"), Object::Num(12.0));
         assert_eq!(
-            e.values.get(&"Two".to_string()).unwrap(),
+            e.values.get(&Symbol::intern("Two")).unwrap(),
             &Object::Num(12.0)
         );
     }
@@ -122,31 +172,31 @@ mod tests {
     #[test]
     fn can_look_up_a_variable() {
         let mut e = Environment::new();
-        e.define(&"Three".to_string(), Object::Str("foo".to_string()));
+        e.define(Symbol::intern("Three"), Object::Str("foo".to_string()));
 
-        let three_tok = Token::new(TokenType::Identifier, "Three".to_string(), None, 0);
+        let three_tok = Token::new(TokenType::Identifier, "Three", None, 0);
         assert_eq!(e.get(&three_tok).ok(), Some(Object::Str("foo".to_string())));
     }
 
     #[test]
     fn error_when_variable_undefined() {
         let e = Environment::new();
-        let three_tok = Token::new(TokenType::Identifier, "Three".to_string(), None, 0);
+        let three_tok = Token::new(TokenType::Identifier, "Three", None, 0);
         assert!(e.get(&three_tok).is_err());
     }
 
     #[test]
     fn error_when_assigning_to_undefined_variable() {
         let mut e = Environment::new();
-        let four_tok = Token::new(TokenType::Identifier, "Four".to_string(), None, 0);
+        let four_tok = Token::new(TokenType::Identifier, "Four", None, 0);
         assert!(e.assign(&four_tok, Object::Nil).is_err());
     }
 
     #[test]
     fn can_reassign_existing_variable() {
         let mut e = Environment::new();
-        let four_tok = Token::new(TokenType::Identifier, "Four".to_string(), None, 0);
-        e.define(&"Four".to_string(), Object::Num(73.1));
+        let four_tok = Token::new(TokenType::Identifier, "Four", None, 0);
+        e.define(Symbol::intern("Four"), Object::Num(73.1));
         assert!(e.assign(&four_tok, Object::Num(89.5)).is_ok());
         assert_eq!(e.get(&four_tok).ok(), Some(Object::Num(89.5)));
     }
@@ -162,10 +212,10 @@ mod tests {
     fn can_read_from_enclosed_environment() {
         let e = Rc::new(RefCell::new(Environment::new()));
         e.borrow_mut()
-            .define(&"Five".to_string(), Object::Num(77.8));
+            .define(Symbol::intern("Five"), Object::Num(77.8));
 
         let f = Environment::new_with_enclosing(Rc::clone(&e));
-        let five_tok = Token::new(TokenType::Identifier, "Five".to_string(), None, 0);
+        let five_tok = Token::new(TokenType::Identifier, "Five", None, 0);
         assert_eq!(f.get(&five_tok).ok(), Some(Object::Num(77.8)));
     }
 
@@ -173,11 +223,53 @@ mod tests {
     fn can_assign_to_enclosed_environment() {
         let e = Rc::new(RefCell::new(Environment::new()));
         e.borrow_mut()
-            .define(&"Five".to_string(), Object::Num(77.8));
+            .define(Symbol::intern("Five"), Object::Num(77.8));
 
         let mut f = Environment::new_with_enclosing(Rc::clone(&e));
-        let five_tok = Token::new(TokenType::Identifier, "Five".to_string(), None, 0);
+        let five_tok = Token::new(TokenType::Identifier, "Five", None, 0);
         assert!(f.assign(&five_tok, Object::Num(91.2)).is_ok());
         assert_eq!(f.get(&five_tok).ok(), Some(Object::Num(91.2)));
     }
+
+    #[test]
+    fn error_when_assigning_to_a_constant() {
+        let mut e = Environment::new();
+        e.define_constant(Symbol::intern("Six"), Object::Num(6.0));
+        let six_tok = Token::new(TokenType::Identifier, "Six", None, 0);
+        assert!(e.assign(&six_tok, Object::Nil).is_err());
+        assert_eq!(e.get(&six_tok).ok(), Some(Object::Num(6.0)));
+    }
+
+    #[test]
+    fn can_redefine_a_constant_as_a_variable() {
+        let mut e = Environment::new();
+        e.define_constant(Symbol::intern("Seven"), Object::Num(7.0));
+        e.define(Symbol::intern("Seven"), Object::Nil);
+        let seven_tok = Token::new(TokenType::Identifier, "Seven", None, 0);
+        assert!(e.assign(&seven_tok, Object::Num(8.0)).is_ok());
+    }
+
+    #[test]
+    fn locals_are_stored_in_slots() {
+        let e = Rc::new(RefCell::new(Environment::new()));
+        let mut f = Environment::new_with_enclosing(Rc::clone(&e));
+        f.define(Symbol::intern("Eight"), Object::Num(8.0));
+        f.define(Symbol::intern("Nine"), Object::Num(9.0));
+        assert!(f.values.is_empty());
+
+        let nine = Slot { depth: 0, index: 1 };
+        assert_eq!(f.get_at(nine), Object::Num(9.0));
+        f.assign_at(nine, Object::Nil);
+        assert_eq!(f.get_at(nine), Object::Nil);
+    }
+
+    #[test]
+    fn can_read_slots_from_an_enclosing_environment() {
+        let e = Rc::new(RefCell::new(Environment::new()));
+        let f = Rc::new(RefCell::new(Environment::new_with_enclosing(Rc::clone(&e))));
+        f.borrow_mut()
+            .define(Symbol::intern("Ten"), Object::Num(10.0));
+        let g = Environment::new_with_enclosing(Rc::clone(&f));
+        assert_eq!(g.get_at(Slot { depth: 1, index: 0 }), Object::Num(10.0));
+    }
 }
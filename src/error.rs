@@ -1,13 +1,35 @@
+use std::rc::Rc;
+
+use crate::lox_function::*;
 use crate::object::*;
 use crate::token::*;
 use crate::token_type::*;
 
 pub enum LoxResult {
-    ParseError { token: Token, message: String },
-    RuntimeError { token: Token, message: String },
-    Error { line: usize, message: String },
-    SystemError { message: String },
-    ReturnValue { value: Object },
+    ParseError {
+        token: Token,
+        message: String,
+    },
+    RuntimeError {
+        token: Token,
+        message: String,
+    },
+    Error {
+        line: usize,
+        message: String,
+    },
+    SystemError {
+        message: String,
+    },
+    ReturnValue {
+        value: Object,
+    },
+    // A call in tail position, unwinding to the call it replaces
+    TailCall {
+        function: Rc<LoxFunction>,
+        slots: Vec<Option<Object>>,
+        rest: Vec<Object>,
+    },
     Break,
     Fail,
 }
@@ -83,7 +105,7 @@ impl LoxResult {
             LoxResult::SystemError { message } => {
                 eprintln!("System Error: {message}");
             }
-            LoxResult::Break | LoxResult::ReturnValue { .. } => {}
+            LoxResult::Break | LoxResult::ReturnValue { .. } | LoxResult::TailCall { .. } => {}
             LoxResult::Fail => {
                 panic!("should not get here")
             }
@@ -0,0 +1,401 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use crate::environment::*;
+use crate::lox_class::*;
+use crate::lox_function::*;
+use crate::lox_instance::*;
+use crate::lox_trait::*;
+use crate::object::*;
+use crate::vm::*;
+
+// Values are reference counted, which frees everything except cycles: a
+// recursive local function holds the environment that holds it, a bound
+// method holds its instance, and so on. The collector finds those cycles
+// the way CPython does. For every heap value it counts how many of its
+// references come from other heap values. Values with references from
+// anywhere else (the interpreter, the VM stack, Rust locals) are roots, and
+// whatever roots can't reach is garbage, so it gets cleared to break the
+// cycles it is part of.
+
+// Anything a value holds an `Rc` to that can hold values in turn
+pub trait Trace {
+    fn trace(&self, edges: &mut Vec<Node>);
+}
+
+// A strong handle to one heap value
+#[derive(Clone)]
+pub enum Node {
+    Environment(Rc<RefCell<Environment>>),
+    Function(Rc<LoxFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<LoxInstance>),
+    List(Rc<RefCell<Vec<Object>>>),
+    Trait(Rc<LoxTrait>),
+    Closure(Rc<Closure>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+}
+
+enum WeakNode {
+    Environment(Weak<RefCell<Environment>>),
+    Class(Weak<LoxClass>),
+    Instance(Weak<LoxInstance>),
+    List(Weak<RefCell<Vec<Object>>>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+}
+
+impl Node {
+    pub fn of(value: &Object) -> Option<Node> {
+        match value {
+            Object::Func(f) => Some(Node::Function(Rc::clone(f))),
+            Object::Class(c) => Some(Node::Class(Rc::clone(c))),
+            Object::Instance(i) => Some(Node::Instance(Rc::clone(i))),
+            Object::List(l) => Some(Node::List(Rc::clone(l))),
+            Object::Trait(t) => Some(Node::Trait(Rc::clone(t))),
+            _ => None,
+        }
+    }
+
+    fn address(&self) -> *const () {
+        match self {
+            Node::Environment(e) => Rc::as_ptr(e) as *const (),
+            Node::Function(f) => Rc::as_ptr(f) as *const (),
+            Node::Class(c) => Rc::as_ptr(c) as *const (),
+            Node::Instance(i) => Rc::as_ptr(i) as *const (),
+            Node::List(l) => Rc::as_ptr(l) as *const (),
+            Node::Trait(t) => Rc::as_ptr(t) as *const (),
+            Node::Closure(c) => Rc::as_ptr(c) as *const (),
+            Node::Upvalue(u) => Rc::as_ptr(u) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(e) => Rc::strong_count(e),
+            Node::Function(f) => Rc::strong_count(f),
+            Node::Class(c) => Rc::strong_count(c),
+            Node::Instance(i) => Rc::strong_count(i),
+            Node::List(l) => Rc::strong_count(l),
+            Node::Trait(t) => Rc::strong_count(t),
+            Node::Closure(c) => Rc::strong_count(c),
+            Node::Upvalue(u) => Rc::strong_count(u),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Node::Environment(_) => "environments",
+            Node::Function(_) => "functions",
+            Node::Class(_) => "classes",
+            Node::Instance(_) => "instances",
+            Node::List(_) => "lists",
+            Node::Trait(_) => "traits",
+            Node::Closure(_) => "closures",
+            Node::Upvalue(_) => "upvalues",
+        }
+    }
+
+    // Returns false when the value is mutably borrowed right now, so its
+    // references can't be followed. The collector keeps such values.
+    fn trace(&self, edges: &mut Vec<Node>) -> bool {
+        match self {
+            Node::Environment(e) => match e.try_borrow() {
+                Ok(e) => e.trace(edges),
+                Err(_) => return false,
+            },
+            Node::List(l) => match l.try_borrow() {
+                Ok(items) => edges.extend(items.iter().filter_map(Node::of)),
+                Err(_) => return false,
+            },
+            Node::Upvalue(u) => match u.try_borrow() {
+                Ok(u) => u.trace(edges),
+                Err(_) => return false,
+            },
+            Node::Function(f) => f.trace(edges),
+            Node::Class(c) => c.trace(edges),
+            Node::Instance(i) => i.trace(edges),
+            Node::Trait(t) => t.trace(edges),
+            Node::Closure(c) => c.trace(edges),
+        }
+        true
+    }
+
+    // Drops the references a garbage value holds. Every cycle passes through
+    // something mutable, so clearing those is enough to break it.
+    fn clear(&self) {
+        match self {
+            Node::Environment(e) => {
+                if let Ok(mut e) = e.try_borrow_mut() {
+                    e.clear();
+                }
+            }
+            Node::List(l) => {
+                if let Ok(mut items) = l.try_borrow_mut() {
+                    items.clear();
+                }
+            }
+            Node::Upvalue(u) => {
+                if let Ok(mut u) = u.try_borrow_mut() {
+                    *u = Upvalue::Closed(Object::Nil);
+                }
+            }
+            Node::Class(c) => c.clear_fields(),
+            Node::Instance(i) => i.clear_fields(),
+            Node::Function(_) | Node::Trait(_) | Node::Closure(_) => {}
+        }
+    }
+
+    fn downgrade(&self) -> Option<WeakNode> {
+        match self {
+            Node::Environment(e) => Some(WeakNode::Environment(Rc::downgrade(e))),
+            Node::Class(c) => Some(WeakNode::Class(Rc::downgrade(c))),
+            Node::Instance(i) => Some(WeakNode::Instance(Rc::downgrade(i))),
+            Node::List(l) => Some(WeakNode::List(Rc::downgrade(l))),
+            Node::Upvalue(u) => Some(WeakNode::Upvalue(Rc::downgrade(u))),
+            Node::Function(_) | Node::Trait(_) | Node::Closure(_) => None,
+        }
+    }
+}
+
+impl WeakNode {
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            WeakNode::Environment(e) => e.upgrade().map(Node::Environment),
+            WeakNode::Class(c) => c.upgrade().map(Node::Class),
+            WeakNode::Instance(i) => i.upgrade().map(Node::Instance),
+            WeakNode::List(l) => l.upgrade().map(Node::List),
+            WeakNode::Upvalue(u) => u.upgrade().map(Node::Upvalue),
+        }
+    }
+}
+
+// Collect once this many values have been tracked since the last collection,
+// or twice what survived it if that is more
+const MIN_THRESHOLD: usize = 50_000;
+
+// The mutable values, which every cycle must pass through. The rest of the
+// heap is found by tracing from them.
+struct Heap {
+    tracked: Vec<WeakNode>,
+    since_collection: usize,
+    threshold: usize,
+    collections: usize,
+    collected: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const {
+        RefCell::new(Heap {
+            tracked: Vec::new(),
+            since_collection: 0,
+            threshold: MIN_THRESHOLD,
+            collections: 0,
+            collected: 0,
+        })
+    };
+}
+
+// Registers a newly allocated value with the collector
+pub fn track(node: Node) {
+    let Some(weak) = node.downgrade() else {
+        return;
+    };
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.push(weak);
+        heap.since_collection += 1;
+        heap.since_collection >= heap.threshold
+    });
+    drop(node);
+    if due {
+        collect();
+    }
+}
+
+// Every live heap value reachable from a tracked one, with how many of
+// its references come from the others and which of them it refers to
+struct Graph {
+    nodes: Vec<Node>,
+    internal: Vec<usize>,
+    edges: Vec<Vec<usize>>,
+    opaque: Vec<bool>,
+}
+
+fn graph() -> Graph {
+    let start: Vec<Node> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.retain(|weak| weak.upgrade().is_some());
+        heap.tracked.iter().filter_map(WeakNode::upgrade).collect()
+    });
+
+    let mut graph = Graph {
+        nodes: Vec::new(),
+        internal: Vec::new(),
+        edges: Vec::new(),
+        opaque: Vec::new(),
+    };
+    let mut index = HashMap::new();
+    for node in start {
+        graph.add(&mut index, node);
+    }
+
+    let mut i = 0;
+    let mut children = Vec::new();
+    while i < graph.nodes.len() {
+        if !graph.nodes[i].trace(&mut children) {
+            graph.opaque[i] = true;
+        }
+        for child in children.drain(..) {
+            let j = graph.add(&mut index, child);
+            graph.internal[j] += 1;
+            graph.edges[i].push(j);
+        }
+        i += 1;
+    }
+    graph
+}
+
+impl Graph {
+    fn add(&mut self, index: &mut HashMap<*const (), usize>, node: Node) -> usize {
+        *index.entry(node.address()).or_insert_with(|| {
+            self.nodes.push(node);
+            self.internal.push(0);
+            self.edges.push(Vec::new());
+            self.opaque.push(false);
+            self.nodes.len() - 1
+        })
+    }
+
+    // Marks everything reachable from a value that is referenced from
+    // outside the heap. The graph itself holds one reference to each value.
+    fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut pending: Vec<usize> = (0..self.nodes.len())
+            .filter(|&i| self.opaque[i] || self.nodes[i].strong_count() > self.internal[i] + 1)
+            .collect();
+
+        while let Some(i) = pending.pop() {
+            if !reachable[i] {
+                reachable[i] = true;
+                pending.extend(self.edges[i].iter().copied());
+            }
+        }
+        reachable
+    }
+}
+
+// Frees unreachable cycles and returns how many values they held
+pub fn collect() -> usize {
+    let graph = graph();
+    let reachable = graph.reachable();
+
+    let garbage: Vec<&Node> = graph
+        .nodes
+        .iter()
+        .zip(&reachable)
+        .filter(|(_, reachable)| !**reachable)
+        .map(|(node, _)| node)
+        .collect();
+    for node in &garbage {
+        node.clear();
+    }
+    let freed = garbage.len();
+    let survivors = graph.nodes.len() - freed;
+    drop(garbage);
+    drop(graph);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.retain(|weak| weak.upgrade().is_some());
+        heap.since_collection = 0;
+        heap.threshold = MIN_THRESHOLD.max(survivors * 2);
+        heap.collections += 1;
+        heap.collected += freed;
+    });
+    freed
+}
+
+pub struct Stats {
+    pub live: Vec<(&'static str, usize)>,
+    pub collections: usize,
+    pub collected: usize,
+}
+
+pub fn stats() -> Stats {
+    let mut live: Vec<(&'static str, usize)> = Vec::new();
+    for node in graph().nodes {
+        match live.iter_mut().find(|(kind, _)| *kind == node.kind()) {
+            Some((_, count)) => *count += 1,
+            None => live.push((node.kind(), 1)),
+        }
+    }
+    live.sort();
+
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        Stats {
+            live,
+            collections: heap.collections,
+            collected: heap.collected,
+        }
+    })
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total: usize = self.live.iter().map(|(_, count)| count).sum();
+        write!(f, "live: {total}")?;
+        if !self.live.is_empty() {
+            let kinds: Vec<String> = self
+                .live
+                .iter()
+                .map(|(kind, count)| format!("{kind} {count}"))
+                .collect();
+            write!(f, " ({})", kinds.join(", "))?;
+        }
+        write!(
+            f,
+            ", collections: {}, collected: {}",
+            self.collections, self.collected
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn self_referencing_list() -> Object {
+        let list = Object::list(Vec::new());
+        if let Object::List(items) = &list {
+            items.borrow_mut().push(list.clone());
+        }
+        list
+    }
+
+    #[test]
+    fn frees_unreachable_cycles() {
+        let list = self_referencing_list();
+        let Object::List(items) = &list else {
+            unreachable!()
+        };
+        let weak = Rc::downgrade(items);
+        drop(list);
+
+        assert!(weak.upgrade().is_some());
+        assert_eq!(collect(), 1);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn keeps_cycles_that_are_still_referenced() {
+        let list = self_referencing_list();
+        let inner = Object::list(vec![list.clone()]);
+
+        assert_eq!(collect(), 0);
+        assert!(matches!(&list, Object::List(items) if items.borrow().len() == 1));
+        drop(inner);
+    }
+}
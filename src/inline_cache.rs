@@ -0,0 +1,87 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use crate::lox_class::*;
+use crate::lox_function::*;
+use crate::object::*;
+
+// Remembers what a property lookup found on the last class seen at one
+// site in the code. Method tables don't change once a class is built, so
+// the answer holds until an instance of some other class comes by. Both
+// sides are weak: methods own the code that owns the cache.
+#[derive(Default)]
+pub struct InlineCache {
+    entry: RefCell<Option<Entry>>,
+}
+
+struct Entry {
+    klass: Weak<LoxClass>,
+    method: Option<Weak<LoxFunction>>,
+}
+
+impl InlineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lookup(
+        &self,
+        klass: &Rc<LoxClass>,
+        find: impl FnOnce() -> Option<Object>,
+    ) -> Option<Object> {
+        if let Some(entry) = &*self.entry.borrow() {
+            if entry.klass.as_ptr() == Rc::as_ptr(klass) {
+                return entry
+                    .method
+                    .as_ref()
+                    .and_then(Weak::upgrade)
+                    .map(Object::Func);
+            }
+        }
+
+        let found = find();
+        let method = match &found {
+            Some(Object::Func(method)) => Some(Rc::downgrade(method)),
+            Some(_) => return found,
+            None => None,
+        };
+        self.entry.replace(Some(Entry {
+            klass: Rc::downgrade(klass),
+            method,
+        }));
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn class(name: &str) -> Rc<LoxClass> {
+        Rc::new(LoxClass::new(
+            name,
+            None,
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        ))
+    }
+
+    #[test]
+    fn looks_up_again_only_for_another_class() {
+        let cache = InlineCache::new();
+        let (a, b) = (class("A"), class("B"));
+        let lookups = RefCell::new(0);
+        let find = || {
+            *lookups.borrow_mut() += 1;
+            None
+        };
+
+        cache.lookup(&a, find);
+        cache.lookup(&a, find);
+        assert_eq!(*lookups.borrow(), 1);
+        cache.lookup(&b, find);
+        assert_eq!(*lookups.borrow(), 2);
+    }
+}
@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::rc::Rc;
@@ -7,72 +7,79 @@ use crate::callable::*;
 use crate::environment::*;
 use crate::error::*;
 use crate::expr::*;
+use crate::inline_cache::*;
 use crate::lox_class::*;
 use crate::lox_function::*;
+use crate::lox_instance::*;
+use crate::lox_trait::*;
 use crate::native_functions::*;
 use crate::object::*;
+use crate::pattern::*;
+use crate::profiler::*;
 use crate::stmt::*;
+use crate::symbol::*;
 use crate::token::*;
 use crate::token_type::*;
 
+// A callable value, plus the class itself when the callee is a class
+type Callee = (Rc<dyn LoxCallable>, Option<Rc<LoxClass>>);
+
+// Where a for-in loop is in the values it walks over
+pub enum Iteration {
+    Items(std::vec::IntoIter<Object>),
+    Instance(Rc<LoxInstance>),
+}
+
 pub struct Interpreter {
     pub globals: Rc<RefCell<Environment>>,
     environment: RefCell<Rc<RefCell<Environment>>>,
-    locals: RefCell<HashMap<Rc<Expr>, usize>>,
+    profiler: Option<Box<RefCell<Profiler>>>,
 }
 
 impl StmtVisitor<()> for Interpreter {
-    fn visit_class_stmt(&self, _: Rc<Stmt>, stmt: &ClassStmt) -> Result<(), LoxResult> {
+    fn visit_class_stmt(&self, _: &Rc<Stmt>, stmt: &ClassStmt) -> Result<(), LoxResult> {
         let superclass = if let Some(superclass_expr) = &stmt.superclass {
-            let superclass = self.evaluate(superclass_expr.clone())?;
-
-            if let Object::Class(c) = superclass {
-                Some(c)
-            } else if let Expr::Variable(v) = superclass_expr.deref() {
-                return Err(LoxResult::runtime_error(
-                    &v.name,
-                    "Superclass must be a class.",
-                ));
-            } else {
-                panic!("could not extract variable expr");
-            }
+            let superclass = self.evaluate(superclass_expr)?;
+            Some(self.as_superclass(superclass, &variable_name(superclass_expr))?)
         } else {
             None
         };
 
-        self.environment
-            .borrow()
-            .borrow_mut()
-            .define(&stmt.name.as_string(), Object::Nil);
+        let mut traits = Vec::new();
+        for trait_expr in stmt.traits.iter() {
+            let value = self.evaluate(trait_expr)?;
+            traits.push((variable_name(trait_expr), value));
+        }
+        let own_methods: Vec<Symbol> = stmt
+            .methods
+            .iter()
+            .filter_map(|m| match m.deref() {
+                Stmt::Function(f) => Some(f.name.lexeme()),
+                _ => None,
+            })
+            .collect();
+        let trait_methods = self.trait_methods(traits, &own_methods)?;
 
         let enclosing = if let Some(ref s) = superclass {
             let mut e = Environment::new_with_enclosing(self.environment.borrow().clone());
-            e.define("super", Object::Class(s.clone()));
+            e.define(Symbol::SUPER, Object::Class(s.clone()));
             Some(self.environment.replace(Rc::new(RefCell::new(e))))
         } else {
             None
         };
 
-        let mut methods = HashMap::new();
-        for method in stmt.methods.deref() {
-            if let Stmt::Function(func) = method.deref() {
-                let is_init = func.name.as_string() == "init";
-                let function = Object::Func(Rc::new(LoxFunction::new(
-                    func,
-                    &self.environment.borrow(),
-                    is_init,
-                )));
-                methods.insert(func.name.as_string(), function);
-            } else {
-                panic!("non-function method in class");
-            };
-        }
+        let mut methods = trait_methods;
+        methods.extend(self.method_table(&stmt.methods, true));
+        let class_methods = self.method_table(&stmt.class_methods, false);
+        let setters = self.method_table(&stmt.setters, false);
 
-        let klass = Object::Class(Rc::new(LoxClass::new(
+        let klass = Object::class(LoxClass::new(
             &stmt.name.as_string(),
             superclass,
             methods,
-        )));
+            class_methods,
+            setters,
+        ));
 
         if let Some(previous) = enclosing {
             self.environment.replace(previous);
@@ -81,35 +88,68 @@ impl StmtVisitor<()> for Interpreter {
         self.environment
             .borrow()
             .borrow_mut()
-            .assign(&stmt.name, klass)?;
+            .define(stmt.name.lexeme(), klass);
+
+        Ok(())
+    }
 
+    fn visit_trait_stmt(&self, _: &Rc<Stmt>, stmt: &TraitStmt) -> Result<(), LoxResult> {
+        let methods = self.method_table(&stmt.methods, true);
+        let t = LoxTrait::new(&stmt.name.as_string(), methods);
+        self.environment
+            .borrow()
+            .borrow_mut()
+            .define(stmt.name.lexeme(), Object::Trait(Rc::new(t)));
         Ok(())
     }
 
-    fn visit_return_stmt(&self, _: Rc<Stmt>, stmt: &ReturnStmt) -> Result<(), LoxResult> {
-        if let Some(value) = stmt.value.clone() {
+    fn visit_return_stmt(&self, _: &Rc<Stmt>, stmt: &ReturnStmt) -> Result<(), LoxResult> {
+        if let Some(value) = &stmt.value {
             Err(LoxResult::return_value(self.evaluate(value)?))
         } else {
             Err(LoxResult::return_value(Object::Nil))
         }
     }
 
-    fn visit_function_stmt(&self, _: Rc<Stmt>, stmt: &FunctionStmt) -> Result<(), LoxResult> {
+    fn visit_function_stmt(&self, _: &Rc<Stmt>, stmt: &FunctionStmt) -> Result<(), LoxResult> {
         let function = LoxFunction::new(stmt, self.environment.borrow().deref(), false);
         self.environment
             .borrow()
             .borrow_mut()
-            .define(&stmt.name.as_string(), Object::Func(Rc::new(function)));
+            .define(stmt.name.lexeme(), Object::Func(Rc::new(function)));
         Ok(())
     }
 
-    fn visit_break_stmt(&self, _: Rc<Stmt>, _stmt: &BreakStmt) -> Result<(), LoxResult> {
+    fn visit_break_stmt(&self, _: &Rc<Stmt>, _stmt: &BreakStmt) -> Result<(), LoxResult> {
         Err(LoxResult::Break)
     }
 
-    fn visit_while_stmt(&self, _: Rc<Stmt>, stmt: &WhileStmt) -> Result<(), LoxResult> {
-        while self.is_truthy(&self.evaluate(stmt.condition.clone())?) {
-            match self.execute(stmt.body.clone()) {
+    fn visit_error_stmt(&self, _: &Rc<Stmt>, stmt: &ErrorStmt) -> Result<(), LoxResult> {
+        Err(LoxResult::runtime_error(
+            &stmt.tokens[0],
+            "Can't run code that failed to parse.",
+        ))
+    }
+
+    fn visit_do_stmt(&self, _: &Rc<Stmt>, stmt: &DoStmt) -> Result<(), LoxResult> {
+        loop {
+            match self.execute(&stmt.body) {
+                Err(LoxResult::Break) => break,
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+
+            if !self.is_truthy(&self.evaluate(&stmt.condition)?) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_while_stmt(&self, _: &Rc<Stmt>, stmt: &WhileStmt) -> Result<(), LoxResult> {
+        while self.is_truthy(&self.evaluate(&stmt.condition)?) {
+            match self.execute(&stmt.body) {
                 Err(LoxResult::Break) => break,
                 Err(e) => return Err(e),
                 Ok(_) => {}
@@ -119,150 +159,196 @@ impl StmtVisitor<()> for Interpreter {
         Ok(())
     }
 
-    fn visit_if_stmt(&self, _: Rc<Stmt>, stmt: &IfStmt) -> Result<(), LoxResult> {
-        if self.is_truthy(&self.evaluate(stmt.condition.clone())?) {
-            self.execute(stmt.then_branch.clone())
-        } else if let Some(else_branch) = stmt.else_branch.clone() {
+    fn visit_match_stmt(&self, _: &Rc<Stmt>, stmt: &MatchStmt) -> Result<(), LoxResult> {
+        let value = self.evaluate(&stmt.subject)?;
+
+        for arm in stmt.arms.iter() {
+            let matched = arm.patterns.iter().any(|pattern| match pattern {
+                Pattern::Wildcard => true,
+                Pattern::Literal(literal) => value.is_equal(literal),
+            });
+
+            if matched {
+                return self.execute(&arm.body);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_forin_stmt(&self, _: &Rc<Stmt>, stmt: &ForInStmt) -> Result<(), LoxResult> {
+        let iterable = self.evaluate(&stmt.iterable)?;
+        let mut iteration = self.iterate(iterable, &stmt.name)?;
+        let body = Rc::new(vec![stmt.body.clone()]);
+
+        while let Some(value) = self.next_item(&mut iteration, &stmt.name)? {
+            if !self.for_in_iteration(stmt, &body, value)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_if_stmt(&self, _: &Rc<Stmt>, stmt: &IfStmt) -> Result<(), LoxResult> {
+        if self.is_truthy(&self.evaluate(&stmt.condition)?) {
+            self.execute(&stmt.then_branch)
+        } else if let Some(else_branch) = &stmt.else_branch {
             self.execute(else_branch)
         } else {
             Ok(())
         }
     }
 
-    fn visit_block_stmt(&self, _: Rc<Stmt>, stmt: &BlockStmt) -> Result<(), LoxResult> {
+    fn visit_block_stmt(&self, _: &Rc<Stmt>, stmt: &BlockStmt) -> Result<(), LoxResult> {
         let e = Environment::new_with_enclosing(self.environment.borrow().clone());
-        self.execute_block(&stmt.statements, e)
+        self.execute_block(&stmt.statements, Rc::new(RefCell::new(e)))
     }
 
-    fn visit_expression_stmt(&self, _: Rc<Stmt>, stmt: &ExpressionStmt) -> Result<(), LoxResult> {
-        self.evaluate(stmt.expression.clone())?;
+    fn visit_expression_stmt(&self, _: &Rc<Stmt>, stmt: &ExpressionStmt) -> Result<(), LoxResult> {
+        self.evaluate(&stmt.expression)?;
         Ok(())
     }
 
-    fn visit_print_stmt(&self, _: Rc<Stmt>, stmt: &PrintStmt) -> Result<(), LoxResult> {
-        let value = self.evaluate(stmt.expression.clone())?;
-        println!("{value}");
+    fn visit_print_stmt(&self, _: &Rc<Stmt>, stmt: &PrintStmt) -> Result<(), LoxResult> {
+        let value = self.evaluate(&stmt.expression)?;
+        println!("{}", self.stringify(&value, &stmt.keyword)?);
         Ok(())
     }
 
-    fn visit_var_stmt(&self, _: Rc<Stmt>, stmt: &VarStmt) -> Result<(), LoxResult> {
-        let value = if let Some(initializer) = stmt.initializer.clone() {
+    fn visit_var_stmt(&self, _: &Rc<Stmt>, stmt: &VarStmt) -> Result<(), LoxResult> {
+        let value = if let Some(initializer) = &stmt.initializer {
             self.evaluate(initializer)?
         } else {
             Object::Nil
         };
 
-        self.environment
-            .borrow()
-            .borrow_mut()
-            .define(&stmt.name.as_string(), value);
+        let environment = self.environment.borrow();
+        if stmt.is_const {
+            environment
+                .borrow_mut()
+                .define_constant(stmt.name.lexeme(), value);
+        } else {
+            environment.borrow_mut().define(stmt.name.lexeme(), value);
+        }
         Ok(())
     }
 }
 
 impl ExprVisitor<Object> for Interpreter {
-    fn visit_super_expr(&self, wrapper: Rc<Expr>, expr: &SuperExpr) -> Result<Object, LoxResult> {
-        let distance = *self.locals.borrow().get(&wrapper).unwrap();
-        let superclass = if let Ok(Object::Class(superclass)) =
-            self.environment.borrow().borrow().get_at(distance, "super")
-        {
-            superclass
-        } else {
-            panic!("Unable to extract superclass");
-        };
-
-        let object = self
-            .environment
-            .borrow()
-            .borrow()
-            .get_at(distance - 1, "this")
-            .ok()
-            .unwrap();
+    fn visit_error_expr(&self, _: &Rc<Expr>, expr: &ErrorExpr) -> Result<Object, LoxResult> {
+        Err(LoxResult::runtime_error(
+            &expr.token,
+            "Can't evaluate code that failed to parse.",
+        ))
+    }
 
-        if let Some(method) = superclass.find_method(&expr.method.as_string()) {
-            if let Object::Func(func) = method {
-                Ok(func.bind(&object))
+    fn visit_super_expr(&self, _: &Rc<Expr>, expr: &SuperExpr) -> Result<Object, LoxResult> {
+        let slot = expr.slot.get().unwrap();
+        let superclass =
+            if let Object::Class(superclass) = self.environment.borrow().borrow().get_at(slot) {
+                superclass
             } else {
-                panic!("method was not a function");
-            }
-        } else {
-            Err(LoxResult::runtime_error(
-                &expr.method,
-                &format!("Undefined property '{}'.", expr.method.as_string()),
-            ))
-        }
+                panic!("Unable to extract superclass");
+            };
+
+        let object = self.environment.borrow().borrow().get_at(Slot {
+            depth: slot.depth - 1,
+            index: 0,
+        });
+
+        self.super_method(&superclass, object, &expr.method)
     }
 
-    fn visit_this_expr(&self, wrapper: Rc<Expr>, expr: &ThisExpr) -> Result<Object, LoxResult> {
-        self.look_up_variable(&expr.keyword, wrapper)
+    fn visit_this_expr(&self, _: &Rc<Expr>, expr: &ThisExpr) -> Result<Object, LoxResult> {
+        self.look_up_variable(&expr.keyword, &expr.slot)
     }
 
-    fn visit_set_expr(&self, _: Rc<Expr>, expr: &SetExpr) -> Result<Object, LoxResult> {
-        let object = self.evaluate(expr.object.clone())?;
-        if let Object::Instance(inst) = object {
-            let value = self.evaluate(expr.value.clone())?;
-            inst.set(&expr.name, value.clone());
-            Ok(value)
-        } else {
-            Err(LoxResult::runtime_error(
+    fn visit_set_expr(&self, _: &Rc<Expr>, expr: &SetExpr) -> Result<Object, LoxResult> {
+        let object = self.evaluate(&expr.object)?;
+        if !matches!(object, Object::Instance(_) | Object::Class(_)) {
+            return Err(LoxResult::runtime_error(
                 &expr.name,
                 "Only instances have fields.",
-            ))
+            ));
         }
+        let value = self.evaluate(&expr.value)?;
+        self.set_property(object, &expr.name, value.clone(), Some(&expr.cache))?;
+        Ok(value)
     }
 
-    fn visit_get_expr(&self, _: Rc<Expr>, expr: &GetExpr) -> Result<Object, LoxResult> {
-        let object = self.evaluate(expr.object.clone())?;
-        if let Object::Instance(inst) = object {
-            Ok(inst.get(&expr.name, &inst)?)
-        } else {
-            Err(LoxResult::runtime_error(
-                &expr.name,
-                "Only instances have properties.",
-            ))
-        }
+    fn visit_get_expr(&self, _: &Rc<Expr>, expr: &GetExpr) -> Result<Object, LoxResult> {
+        let object = self.evaluate(&expr.object)?;
+        self.get_property(object, &expr.name, Some(&expr.cache))
     }
 
-    fn visit_call_expr(&self, _: Rc<Expr>, expr: &CallExpr) -> Result<Object, LoxResult> {
-        let callee = self.evaluate(expr.callee.clone())?;
+    fn visit_index_expr(&self, _: &Rc<Expr>, expr: &IndexExpr) -> Result<Object, LoxResult> {
+        let object = self.evaluate(&expr.object)?;
+        let index = self.evaluate(&expr.index)?;
+        self.index(object, index, &expr.bracket)
+    }
+
+    fn visit_call_expr(&self, _: &Rc<Expr>, expr: &CallExpr) -> Result<Object, LoxResult> {
+        // A method called right away runs without being bound first. Tail
+        // calls still bind it, since they hand a function to the caller.
+        let (callee, receiver) = match expr.callee.deref() {
+            Expr::Get(get) if !expr.tail.get() => {
+                let object = self.evaluate(&get.object)?;
+                let method = match &object {
+                    Object::Instance(inst) => inst.method(&get.name, &get.cache),
+                    _ => None,
+                };
+                match method {
+                    Some(method) => (Object::Func(method), Some(object)),
+                    None => (
+                        self.get_property(object, &get.name, Some(&get.cache))?,
+                        None,
+                    ),
+                }
+            }
+            _ => (self.evaluate(&expr.callee)?, None),
+        };
 
         let mut arguments = Vec::new();
-        for argument in expr.arguments.clone() {
-            arguments.push(self.evaluate(argument)?);
+        let mut named = Vec::new();
+        for (argument, name) in expr.arguments.iter().zip(expr.names.iter()) {
+            let value = self.evaluate(argument)?;
+            if let Some(name) = name {
+                named.push((name.dup(), value));
+            } else {
+                arguments.push(value);
+            }
         }
 
-        let (callfunc, klass): (Option<Rc<dyn LoxCallable>>, Option<Rc<LoxClass>>) = match callee {
-            Object::Func(f) => (Some(f), None),
-            Object::Native(n) => (Some(n.func.clone()), None),
-            Object::Class(c) => {
-                let klass = Rc::clone(&c);
-                (Some(c), Some(klass))
+        if let (Object::Func(method), Some(receiver)) = (&callee, &receiver) {
+            return self.invoke(method, receiver, arguments, named, &expr.paren);
+        }
+
+        if expr.tail.get() {
+            if let Object::Func(function) = &callee {
+                if matches!(function.body(), Body::Tree { .. }) {
+                    return self.tail_call(function, arguments, named, &expr.paren);
+                }
             }
-            _ => (None, None),
-        };
+        }
 
-        if let Some(callfunc) = callfunc {
-            if arguments.len() != callfunc.arity() {
-                return Err(LoxResult::runtime_error(
-                    &expr.paren,
-                    &format!(
-                        "Expected {} arguments but got {}.",
-                        callfunc.arity(),
-                        arguments.len()
-                    ),
-                ));
+        // Functions skip the dynamic dispatch in call(), which would
+        // otherwise leave one more frame on the stack per Lox call
+        if let Object::Func(function) = &callee {
+            if named.is_empty() && function.arity().accepts(arguments.len()) {
+                return function.call(self, arguments, None);
             }
-            callfunc.call(self, arguments, klass)
+        }
+
+        if named.is_empty() {
+            self.call(callee, arguments, &expr.paren)
         } else {
-            Err(LoxResult::runtime_error(
-                &expr.paren,
-                "Can only call functions and classes.",
-            ))
+            self.call_named(callee, arguments, named, &expr.paren)
         }
     }
 
-    fn visit_logical_expr(&self, _: Rc<Expr>, expr: &LogicalExpr) -> Result<Object, LoxResult> {
-        let left = self.evaluate(expr.left.clone())?;
+    fn visit_logical_expr(&self, _: &Rc<Expr>, expr: &LogicalExpr) -> Result<Object, LoxResult> {
+        let left = self.evaluate(&expr.left)?;
 
         if expr.operator.is(TokenType::Or) {
             if self.is_truthy(&left) {
@@ -272,17 +358,16 @@ impl ExprVisitor<Object> for Interpreter {
             return Ok(left);
         }
 
-        self.evaluate(expr.right.clone())
+        self.evaluate(&expr.right)
     }
 
-    fn visit_assign_expr(&self, wrapper: Rc<Expr>, expr: &AssignExpr) -> Result<Object, LoxResult> {
-        let value = self.evaluate(expr.value.clone())?;
-        if let Some(distance) = self.locals.borrow().get(&wrapper) {
-            self.environment.borrow().borrow_mut().assign_at(
-                *distance,
-                &expr.name,
-                value.clone(),
-            )?;
+    fn visit_assign_expr(&self, _: &Rc<Expr>, expr: &AssignExpr) -> Result<Object, LoxResult> {
+        let value = self.evaluate(&expr.value)?;
+        if let Some(slot) = expr.slot.get() {
+            self.environment
+                .borrow()
+                .borrow_mut()
+                .assign_at(slot, value.clone());
         } else {
             self.globals
                 .borrow_mut()
@@ -292,194 +377,674 @@ impl ExprVisitor<Object> for Interpreter {
         Ok(value)
     }
 
-    fn visit_literal_expr(&self, _: Rc<Expr>, expr: &LiteralExpr) -> Result<Object, LoxResult> {
+    fn visit_literal_expr(&self, _: &Rc<Expr>, expr: &LiteralExpr) -> Result<Object, LoxResult> {
         Ok(expr.value.clone().unwrap())
     }
 
-    fn visit_grouping_expr(&self, _: Rc<Expr>, expr: &GroupingExpr) -> Result<Object, LoxResult> {
-        self.evaluate(expr.expression.clone())
+    fn visit_grouping_expr(&self, _: &Rc<Expr>, expr: &GroupingExpr) -> Result<Object, LoxResult> {
+        self.evaluate(&expr.expression)
+    }
+
+    fn visit_binary_expr(&self, _: &Rc<Expr>, expr: &BinaryExpr) -> Result<Object, LoxResult> {
+        let left = self.evaluate(&expr.left)?;
+        let right = self.evaluate(&expr.right)?;
+        self.binary(left, &expr.operator, right)
+    }
+
+    fn visit_unary_expr(&self, _: &Rc<Expr>, expr: &UnaryExpr) -> Result<Object, LoxResult> {
+        let right = self.evaluate(&expr.right)?;
+        self.unary(&expr.operator, right)
+    }
+
+    fn visit_variable_expr(&self, _: &Rc<Expr>, expr: &VariableExpr) -> Result<Object, LoxResult> {
+        self.look_up_variable(&expr.name, &expr.slot)
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+
+        define_natives(&mut globals.borrow_mut());
+
+        Interpreter {
+            globals: Rc::clone(&globals),
+            environment: RefCell::new(Rc::clone(&globals)),
+            profiler: None,
+        }
+    }
+
+    pub fn new_with_profiler() -> Interpreter {
+        Interpreter {
+            profiler: Some(Box::new(RefCell::new(Profiler::new()))),
+            ..Interpreter::new()
+        }
     }
 
-    fn visit_binary_expr(&self, _: Rc<Expr>, expr: &BinaryExpr) -> Result<Object, LoxResult> {
-        let left = self.evaluate(expr.left.clone())?;
-        let right = self.evaluate(expr.right.clone())?;
-        let op = expr.operator.token_type();
+    pub fn profiler(&self) -> Option<&RefCell<Profiler>> {
+        self.profiler.as_deref()
+    }
+
+    // Runs `call` as a call to `name` in the profile, when there is one
+    #[inline]
+    pub fn profile_call<T>(&self, name: Symbol, line: usize, call: impl FnOnce() -> T) -> T {
+        match &self.profiler {
+            Some(profiler) => profiled(profiler, name, line, call),
+            None => call(),
+        }
+    }
+
+    fn evaluate(&self, expr: &Rc<Expr>) -> Result<Object, LoxResult> {
+        expr.accept(expr, self)
+    }
+
+    fn method_table(&self, methods: &[Rc<Stmt>], allow_init: bool) -> HashMap<Symbol, Object> {
+        let mut table = HashMap::new();
+        for method in methods {
+            if let Stmt::Function(func) = method.deref() {
+                let is_init = allow_init && func.name.lexeme() == Symbol::INIT;
+                let function = Object::Func(Rc::new(LoxFunction::new(
+                    func,
+                    &self.environment.borrow(),
+                    is_init,
+                )));
+                table.insert(func.name.lexeme(), function);
+            } else {
+                panic!("non-function method in class");
+            };
+        }
+        table
+    }
+
+    fn callable(&self, callee: Object) -> Option<Callee> {
+        match callee {
+            Object::Func(f) => Some((f, None)),
+            Object::Class(c) => {
+                let klass = Rc::clone(&c);
+                Some((c, Some(klass)))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn call(
+        &self,
+        callee: Object,
+        arguments: Vec<Object>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        if let Object::Native(native) = callee {
+            check_arity(native.arity(), arguments.len(), paren)?;
+            native.call(self, arguments, paren)
+        } else if let Some((callfunc, klass)) = self.callable(callee) {
+            check_arity(callfunc.arity(), arguments.len(), paren)?;
+            callfunc.call(self, arguments, klass)
+        } else {
+            Err(LoxResult::runtime_error(
+                paren,
+                "Can only call functions and classes.",
+            ))
+        }
+    }
+
+    fn invoke(
+        &self,
+        method: &LoxFunction,
+        receiver: &Object,
+        arguments: Vec<Object>,
+        named: Vec<(Token, Object)>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        if named.is_empty() {
+            check_arity(method.arity(), arguments.len(), paren)?;
+        }
+        let (slots, rest) = method.bind_arguments(arguments, named, paren)?;
+        method.call_method(self, receiver, slots, rest)
+    }
+
+    // Leaves the current function and hands the call to the loop in
+    // LoxFunction::invoke, after checking the arguments as a call would.
+    // Kept out of line so visit_call_expr keeps a small frame.
+    #[inline(never)]
+    fn tail_call(
+        &self,
+        function: &Rc<LoxFunction>,
+        arguments: Vec<Object>,
+        named: Vec<(Token, Object)>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        if named.is_empty() {
+            check_arity(function.arity(), arguments.len(), paren)?;
+        }
+        let (slots, rest) = function.bind_arguments(arguments, named, paren)?;
+        Err(LoxResult::TailCall {
+            function: Rc::clone(function),
+            slots,
+            rest,
+        })
+    }
+
+    fn call_method(
+        &self,
+        instance: &Rc<LoxInstance>,
+        name: &str,
+        arguments: Vec<Object>,
+        location: &Token,
+    ) -> Result<Object, LoxResult> {
+        let name = Token::new(TokenType::Identifier, name, None, location.line);
+        let method = instance.get(&name, None, instance, self)?;
+        self.call(method, arguments, &name)
+    }
+
+    // Dispatches a binary operator to a special method on an instance
+    // operand. Kept out of line so visit_binary_expr, which recursive calls
+    // nest through, keeps a small frame.
+    #[inline(never)]
+    fn overloaded_binary(
+        &self,
+        left: &Object,
+        right: &Object,
+        operator: &Token,
+    ) -> Result<Option<Object>, LoxResult> {
+        let method = match operator.token_type() {
+            TokenType::Plus => "__add__",
+            TokenType::Minus => "__sub__",
+            TokenType::Star => "__mul__",
+            TokenType::Slash => "__div__",
+            TokenType::Less => "__lt__",
+            TokenType::LessEqual => "__le__",
+            TokenType::Greater => "__gt__",
+            TokenType::GreaterEqual => "__ge__",
+            TokenType::Equals | TokenType::BangEqual => "__eq__",
+            _ => return Ok(None),
+        };
 
         let result = match (left, right) {
-            (Object::Num(left), Object::Num(right)) => match op {
-                TokenType::Minus => Object::Num(left - right),
-                TokenType::Slash => Object::Num(left / right),
-                TokenType::Star => Object::Num(left * right),
-                TokenType::Plus => Object::Num(left + right),
-                TokenType::Greater => Object::Bool(left > right),
-                TokenType::GreaterEqual => Object::Bool(left >= right),
-                TokenType::Less => Object::Bool(left < right),
-                TokenType::LessEqual => Object::Bool(left <= right),
-                TokenType::BangEqual => Object::Bool(left != right),
-                TokenType::Equals => Object::Bool(left == right),
-                _ => {
-                    todo!("need to work on your code dude");
-                }
-            },
-            (Object::Num(left), Object::Str(right)) => match op {
-                TokenType::Plus => Object::Str(format!("{left}{right}")),
-                TokenType::Equals => Object::Bool(false),
-                TokenType::BangEqual => Object::Bool(true),
-                _ => Object::ArithmeticError,
-            },
-            (Object::Str(left), Object::Num(right)) => match op {
-                TokenType::Plus => Object::Str(format!("{left}{right}")),
-                TokenType::Equals => Object::Bool(false),
-                TokenType::BangEqual => Object::Bool(true),
-                _ => Object::ArithmeticError,
-            },
-            (Object::Str(left), Object::Str(right)) => match op {
-                TokenType::Plus => Object::Str(format!("{left}{right}")),
-                TokenType::BangEqual => Object::Bool(left != right),
-                TokenType::Equals => Object::Bool(left == right),
-                _ => Object::ArithmeticError,
-            },
-            (Object::Bool(left), Object::Bool(right)) => match op {
-                TokenType::BangEqual => Object::Bool(left != right),
-                TokenType::Equals => Object::Bool(left == right),
-                _ => Object::ArithmeticError,
-            },
-            (Object::Bool(_), Object::Str(_)) | (Object::Str(_), Object::Bool(_)) => match op {
-                TokenType::BangEqual => Object::Bool(true),
-                TokenType::Equals => Object::Bool(false),
-                _ => Object::NumsOrStringsError,
-            },
-            (Object::Nil, Object::Nil) => match op {
-                TokenType::BangEqual => Object::Bool(false),
-                TokenType::Equals => Object::Bool(true),
-                _ => Object::NumsOrStringsError,
-            },
-            (Object::Nil, _) | (_, Object::Nil) => match op {
-                TokenType::Equals => Object::Bool(false),
-                TokenType::BangEqual => Object::Bool(true),
-                _ => Object::NumsOrStringsError,
-            },
-            (Object::Func(a), Object::Func(b)) => Object::Bool(Rc::ptr_eq(&a, &b)),
-            (Object::Class(a), Object::Class(b)) => Object::Bool(Rc::ptr_eq(&a, &b)),
-            _ => match op {
-                TokenType::BangEqual => Object::Bool(true),
-                TokenType::Equals => Object::Bool(false),
-                TokenType::Plus => Object::NumsOrStringsError,
-                _ => Object::ArithmeticError,
-            },
+            (Object::Instance(inst), _) if inst.has_property(method) => {
+                self.call_method(inst, method, vec![right.clone()], operator)?
+            }
+            (_, Object::Instance(inst)) if method == "__eq__" && inst.has_property(method) => {
+                self.call_method(inst, method, vec![left.clone()], operator)?
+            }
+            _ => return Ok(None),
         };
 
+        if operator.is(TokenType::BangEqual) {
+            Ok(Some(Object::Bool(!self.is_truthy(&result))))
+        } else {
+            Ok(Some(result))
+        }
+    }
+
+    // The operations below are shared with the bytecode VM, which evaluates
+    // operands itself and hands the values over
+
+    pub fn binary(
+        &self,
+        left: Object,
+        operator: &Token,
+        right: Object,
+    ) -> Result<Object, LoxResult> {
+        let op = operator.token_type();
+
+        if op == TokenType::Is {
+            return match (&left, &right) {
+                (Object::Instance(inst), Object::Class(klass)) => {
+                    Ok(Object::Bool(inst.klass().is_subclass_of(klass)))
+                }
+                (_, Object::Class(_)) => Ok(Object::Bool(false)),
+                _ => Err(LoxResult::runtime_error(
+                    operator,
+                    "Right operand of 'is' must be a class.",
+                )),
+            };
+        }
+
+        if let Some(result) = self.overloaded_binary(&left, &right, operator)? {
+            return Ok(result);
+        }
+
+        if op == TokenType::Plus {
+            match (&left, &right) {
+                (Object::Str(s), Object::Instance(inst)) if inst.has_to_string() => {
+                    let right = self.stringify(&right, operator)?;
+                    return Ok(Object::Str(format!("{s}{right}")));
+                }
+                (Object::Instance(inst), Object::Str(s)) if inst.has_to_string() => {
+                    let left = self.stringify(&left, operator)?;
+                    return Ok(Object::Str(format!("{left}{s}")));
+                }
+                _ => {}
+            }
+        }
+
+        let result = primitive_binary(left, op, right);
+
         match result {
             Object::ArithmeticError => Err(LoxResult::runtime_error(
-                &expr.operator,
+                operator,
                 "Operands must be numbers.",
             )),
             Object::NumsOrStringsError => Err(LoxResult::runtime_error(
-                &expr.operator,
+                operator,
                 "Operands must be two numbers or two strings.",
             )),
             _ => Ok(result),
         }
     }
 
-    fn visit_unary_expr(&self, _: Rc<Expr>, expr: &UnaryExpr) -> Result<Object, LoxResult> {
-        let right = self.evaluate(expr.right.clone())?;
-
-        match expr.operator.token_type() {
+    pub fn unary(&self, operator: &Token, right: Object) -> Result<Object, LoxResult> {
+        match operator.token_type() {
             TokenType::Minus => match right {
                 Object::Num(n) => Ok(Object::Num(-n)),
+                Object::Instance(inst) if inst.has_property("__neg__") => {
+                    self.call_method(&inst, "__neg__", Vec::new(), operator)
+                }
                 _ => Err(LoxResult::runtime_error(
-                    &expr.operator,
+                    operator,
                     "Operand must be a number.",
                 )),
             },
             TokenType::Bang => Ok(Object::Bool(!self.is_truthy(&right))),
             _ => Err(LoxResult::error(
-                expr.operator.line,
+                operator.line,
                 "Unreachable according to Nystrom",
             )),
         }
     }
 
-    fn visit_variable_expr(
+    pub fn get_property(
         &self,
-        wrapper: Rc<Expr>,
-        expr: &VariableExpr,
+        object: Object,
+        name: &Token,
+        cache: Option<&InlineCache>,
     ) -> Result<Object, LoxResult> {
-        self.look_up_variable(&expr.name, wrapper)
+        if let Object::Instance(inst) = object {
+            inst.get(name, cache, &inst, self)
+        } else if let Object::Class(klass) = object {
+            klass.get(name, &klass, self)
+        } else {
+            Err(LoxResult::runtime_error(
+                name,
+                "Only instances have properties.",
+            ))
+        }
     }
-}
 
-impl Interpreter {
-    pub fn new() -> Interpreter {
-        let globals = Rc::new(RefCell::new(Environment::new()));
+    pub fn set_property(
+        &self,
+        object: Object,
+        name: &Token,
+        value: Object,
+        cache: Option<&InlineCache>,
+    ) -> Result<(), LoxResult> {
+        if let Object::Instance(inst) = object {
+            inst.set(name, value, cache, &inst, self)
+        } else if let Object::Class(klass) = object {
+            klass.set(name, value);
+            Ok(())
+        } else {
+            Err(LoxResult::runtime_error(
+                name,
+                "Only instances have fields.",
+            ))
+        }
+    }
 
-        globals.borrow_mut().define(
-            "clock",
-            Object::Native(Rc::new(LoxNative {
-                func: Rc::new(NativeClock {}),
-            })),
-        );
+    pub fn index(
+        &self,
+        object: Object,
+        index: Object,
+        bracket: &Token,
+    ) -> Result<Object, LoxResult> {
+        match object {
+            Object::Instance(inst) if inst.has_property("__index__") => {
+                self.call_method(&inst, "__index__", vec![index], bracket)
+            }
+            Object::Str(s) => {
+                if let Object::Num(n) = index {
+                    if n.fract() == 0.0 && n >= 0.0 {
+                        if let Some(ch) = s.chars().nth(n as usize) {
+                            return Ok(Object::Str(ch.to_string()));
+                        }
+                    }
+                    Err(LoxResult::runtime_error(
+                        bracket,
+                        "String index out of range.",
+                    ))
+                } else {
+                    Err(LoxResult::runtime_error(
+                        bracket,
+                        "String index must be a number.",
+                    ))
+                }
+            }
+            _ => Err(LoxResult::runtime_error(
+                bracket,
+                "Only strings and instances with __index__ can be indexed.",
+            )),
+        }
+    }
 
-        Interpreter {
-            globals: Rc::clone(&globals),
-            environment: RefCell::new(Rc::clone(&globals)),
-            locals: RefCell::new(HashMap::new()),
+    // Looks `method` up starting at `superclass` and binds it to `object`,
+    // which is a class when called from a static method
+    pub fn super_method(
+        &self,
+        superclass: &Rc<LoxClass>,
+        object: Object,
+        method: &Token,
+    ) -> Result<Object, LoxResult> {
+        let found = if let Object::Class(_) = object {
+            superclass.find_class_method(method.lexeme())
+        } else {
+            superclass.find_method(method.lexeme())
+        };
+
+        if let Some(found) = found {
+            if let Object::Func(func) = found {
+                func.bind_property(&object, self)
+            } else {
+                panic!("method was not a function");
+            }
+        } else {
+            Err(LoxResult::runtime_error(
+                method,
+                &format!("Undefined property '{}'.", method.as_string()),
+            ))
+        }
+    }
+
+    pub fn as_superclass(&self, value: Object, name: &Token) -> Result<Rc<LoxClass>, LoxResult> {
+        if let Object::Class(c) = value {
+            Ok(c)
+        } else {
+            Err(LoxResult::runtime_error(
+                name,
+                "Superclass must be a class.",
+            ))
+        }
+    }
+
+    // Collects the methods a class gets from its traits. Two traits may not
+    // provide the same method unless the class overrides it.
+    pub fn trait_methods(
+        &self,
+        traits: Vec<(Token, Object)>,
+        own_methods: &[Symbol],
+    ) -> Result<HashMap<Symbol, Object>, LoxResult> {
+        let mut trait_methods = HashMap::new();
+        let mut provided_by = HashMap::new();
+        for (name, value) in traits {
+            let t = if let Object::Trait(t) = value {
+                t
+            } else {
+                return Err(LoxResult::runtime_error(&name, "Can only include traits."));
+            };
+
+            for (method_name, method) in t.methods() {
+                if own_methods.contains(method_name) {
+                    continue;
+                }
+                if let Some(other) = provided_by.insert(*method_name, t.to_string()) {
+                    return Err(LoxResult::runtime_error(
+                        &name,
+                        &format!(
+                            "Method '{method_name}' from trait '{t}' conflicts with trait '{other}'."
+                        ),
+                    ));
+                }
+                trait_methods.insert(*method_name, method.clone());
+            }
+        }
+        Ok(trait_methods)
+    }
+
+    // Starts a for-in loop: strings and lists are snapshotted, while instances
+    // are walked with next(), on the result of iter() if they have one
+    pub fn iterate(&self, iterable: Object, name: &Token) -> Result<Iteration, LoxResult> {
+        match iterable {
+            Object::Str(s) => Ok(Iteration::Items(
+                s.chars()
+                    .map(|ch| Object::Str(ch.to_string()))
+                    .collect::<Vec<Object>>()
+                    .into_iter(),
+            )),
+            Object::List(list) => Ok(Iteration::Items(list.borrow().clone().into_iter())),
+            Object::Instance(inst) => {
+                if inst.has_property("iter") {
+                    let iter = self.call_method(&inst, "iter", Vec::new(), name)?;
+                    if let Object::Instance(iterator) = iter {
+                        Ok(Iteration::Instance(iterator))
+                    } else {
+                        Err(LoxResult::runtime_error(
+                            name,
+                            "iter() must return an instance.",
+                        ))
+                    }
+                } else {
+                    Ok(Iteration::Instance(inst))
+                }
+            }
+            _ => Err(LoxResult::runtime_error(
+                name,
+                "Can only iterate over lists, strings and iterable instances.",
+            )),
+        }
+    }
+
+    pub fn next_item(
+        &self,
+        iteration: &mut Iteration,
+        name: &Token,
+    ) -> Result<Option<Object>, LoxResult> {
+        match iteration {
+            Iteration::Items(items) => Ok(items.next()),
+            Iteration::Instance(iterator) => {
+                match self.call_method(iterator, "next", Vec::new(), name)? {
+                    Object::Nil => Ok(None),
+                    value => Ok(Some(value)),
+                }
+            }
+        }
+    }
+
+    pub fn call_named(
+        &self,
+        callee: Object,
+        arguments: Vec<Object>,
+        named: Vec<(Token, Object)>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        if let Object::Native(_) = callee {
+            // Natives don't name their parameters
+            let (name, _) = &named[0];
+            Err(LoxResult::runtime_error(
+                name,
+                &format!("Unknown argument '{}'.", name.as_string()),
+            ))
+        } else if let Some((callfunc, klass)) = self.callable(callee) {
+            callfunc.call_named(self, arguments, named, klass, paren)
+        } else {
+            Err(LoxResult::runtime_error(
+                paren,
+                "Can only call functions and classes.",
+            ))
         }
     }
 
-    fn evaluate(&self, expr: Rc<Expr>) -> Result<Object, LoxResult> {
-        expr.accept(expr.clone(), self)
+    fn for_in_iteration(
+        &self,
+        stmt: &ForInStmt,
+        body: &Rc<Vec<Rc<Stmt>>>,
+        value: Object,
+    ) -> Result<bool, LoxResult> {
+        let mut e = Environment::new_with_enclosing(self.environment.borrow().clone());
+        e.define(stmt.name.lexeme(), value);
+        match self.execute_block(body, Rc::new(RefCell::new(e))) {
+            Err(LoxResult::Break) => Ok(false),
+            Err(e) => Err(e),
+            Ok(_) => Ok(true),
+        }
     }
 
-    fn execute(&self, stmt: Rc<Stmt>) -> Result<(), LoxResult> {
-        stmt.accept(stmt.clone(), self)
+    pub fn evaluate_in(
+        &self,
+        expr: &Rc<Expr>,
+        environment: &Rc<RefCell<Environment>>,
+    ) -> Result<Object, LoxResult> {
+        let previous = self.environment.replace(Rc::clone(environment));
+        let result = self.evaluate(expr);
+        self.environment.replace(previous);
+        result
+    }
+
+    fn execute(&self, stmt: &Rc<Stmt>) -> Result<(), LoxResult> {
+        if let Some(profiler) = &self.profiler {
+            if let Some(line) = statement_line(stmt) {
+                profiler.borrow_mut().begin_statement();
+                let result = stmt.accept(stmt, self);
+                profiler.borrow_mut().end_statement(line);
+                return result;
+            }
+        }
+        stmt.accept(stmt, self)
     }
 
     pub fn execute_block(
         &self,
         statements: &Rc<Vec<Rc<Stmt>>>,
-        environment: Environment,
+        environment: Rc<RefCell<Environment>>,
     ) -> Result<(), LoxResult> {
-        let previous = self.environment.replace(Rc::new(RefCell::new(environment)));
+        let previous = self.environment.replace(environment);
 
         let result = statements
             .iter()
-            .try_for_each(|statement| self.execute(statement.clone()));
+            .try_for_each(|statement| self.execute(statement));
 
         self.environment.replace(previous);
 
         result
     }
 
+    pub fn stringify(&self, value: &Object, location: &Token) -> Result<String, LoxResult> {
+        if let Object::Instance(inst) = value {
+            inst.stringify(inst, location, self)
+        } else {
+            Ok(value.to_string())
+        }
+    }
+
     // Anything that is not Nil or False is true
-    fn is_truthy(&self, object: &Object) -> bool {
-        !matches!(object, Object::Nil | Object::Bool(false))
+    pub fn is_truthy(&self, object: &Object) -> bool {
+        object.is_truthy()
     }
 
     pub fn interpret(&self, statements: &[Rc<Stmt>]) -> Result<(), LoxResult> {
-        for statement in statements {
-            self.execute(statement.clone())?;
-        }
-        Ok(())
+        self.profile_call(Symbol::intern("<script>"), 0, || {
+            for statement in statements {
+                self.execute(statement)?;
+            }
+            Ok(())
+        })
     }
 
     pub fn print_environment(&self) {
         println!("{:?}", self.environment);
     }
 
-    pub fn resolve(&self, expr: Rc<Expr>, depth: usize) {
-        self.locals.borrow_mut().insert(expr, depth);
-    }
-
-    fn look_up_variable(&self, name: &Token, expr: Rc<Expr>) -> Result<Object, LoxResult> {
-        if let Some(distance) = self.locals.borrow().get(&expr) {
-            self.environment
-                .borrow()
-                .borrow()
-                .get_at(*distance, &name.as_string())
+    fn look_up_variable(
+        &self,
+        name: &Token,
+        slot: &Cell<Option<Slot>>,
+    ) -> Result<Object, LoxResult> {
+        if let Some(slot) = slot.get() {
+            Ok(self.environment.borrow().borrow().get_at(slot))
         } else {
             self.globals.borrow().get(name)
         }
     }
 }
+
+// The name token of a superclass or trait, which the parser always stores as
+// a variable expression
+fn variable_name(expr: &Expr) -> Token {
+    if let Expr::Variable(v) = expr {
+        v.name.dup()
+    } else {
+        panic!("could not extract variable expr");
+    }
+}
+
+// Out of line, so calls made without --profile don't carry its locals in
+// their frames
+#[inline(never)]
+fn profiled<T>(
+    profiler: &RefCell<Profiler>,
+    name: Symbol,
+    line: usize,
+    call: impl FnOnce() -> T,
+) -> T {
+    profiler.borrow_mut().enter(name, line);
+    let result = call();
+    profiler.borrow_mut().exit();
+    result
+}
+
+fn check_arity(arity: Arity, count: usize, paren: &Token) -> Result<(), LoxResult> {
+    if arity.accepts(count) {
+        Ok(())
+    } else {
+        Err(LoxResult::runtime_error(
+            paren,
+            &format!("Expected {arity} arguments but got {count}."),
+        ))
+    }
+}
+
+// Equality and the operators on numbers and strings. Type errors come back as
+// the ArithmeticError and NumsOrStringsError markers for the caller to report.
+pub fn primitive_binary(left: Object, op: TokenType, right: Object) -> Object {
+    if op == TokenType::Equals {
+        return Object::Bool(left.is_equal(&right));
+    }
+    if op == TokenType::BangEqual {
+        return Object::Bool(!left.is_equal(&right));
+    }
+
+    match (left, right) {
+        (Object::Num(left), Object::Num(right)) => match op {
+            TokenType::Minus => Object::Num(left - right),
+            TokenType::Slash => Object::Num(left / right),
+            TokenType::Star => Object::Num(left * right),
+            TokenType::Plus => Object::Num(left + right),
+            TokenType::Greater => Object::Bool(left > right),
+            TokenType::GreaterEqual => Object::Bool(left >= right),
+            TokenType::Less => Object::Bool(left < right),
+            TokenType::LessEqual => Object::Bool(left <= right),
+            _ => {
+                todo!("need to work on your code dude");
+            }
+        },
+        (Object::Num(left), Object::Str(right)) => match op {
+            TokenType::Plus => Object::Str(format!("{left}{right}")),
+            _ => Object::ArithmeticError,
+        },
+        (Object::Str(left), Object::Num(right)) => match op {
+            TokenType::Plus => Object::Str(format!("{left}{right}")),
+            _ => Object::ArithmeticError,
+        },
+        (Object::Str(left), Object::Str(right)) => match op {
+            TokenType::Plus => Object::Str(format!("{left}{right}")),
+            _ => Object::ArithmeticError,
+        },
+        (Object::Bool(_), Object::Bool(_)) => Object::ArithmeticError,
+        (Object::Bool(_), Object::Str(_))
+        | (Object::Str(_), Object::Bool(_))
+        | (Object::Nil, _)
+        | (_, Object::Nil) => Object::NumsOrStringsError,
+        _ => match op {
+            TokenType::Plus => Object::NumsOrStringsError,
+            _ => Object::ArithmeticError,
+        },
+    }
+}
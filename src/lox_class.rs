@@ -1,17 +1,23 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
 use std::rc::Rc;
 
 use crate::callable::*;
 use crate::error::*;
+use crate::gc::*;
 use crate::interpreter::*;
-use crate::lox_instance::*;
 use crate::object::*;
+use crate::symbol::*;
+use crate::token::*;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct LoxClass {
     name: String,
-    methods: HashMap<String, Object>,
+    methods: HashMap<Symbol, Object>,
+    class_methods: HashMap<Symbol, Object>,
+    setters: HashMap<Symbol, Object>,
+    fields: RefCell<HashMap<Symbol, Object>>,
     superclass: Option<Rc<LoxClass>>,
 }
 
@@ -19,11 +25,16 @@ impl LoxClass {
     pub fn new(
         name: &str,
         superclass: Option<Rc<LoxClass>>,
-        methods: HashMap<String, Object>,
+        methods: HashMap<Symbol, Object>,
+        class_methods: HashMap<Symbol, Object>,
+        setters: HashMap<Symbol, Object>,
     ) -> Self {
         Self {
             name: name.to_string(),
             methods,
+            class_methods,
+            setters,
+            fields: RefCell::new(HashMap::new()),
             superclass,
         }
     }
@@ -34,17 +45,16 @@ impl LoxClass {
         arguments: Vec<Object>,
         klass: Rc<LoxClass>,
     ) -> Result<Object, LoxResult> {
-        let instance = Object::Instance(Rc::new(LoxInstance::new(klass)));
-        if let Some(Object::Func(initializer)) = self.find_method("init") {
-            if let Object::Func(init) = initializer.bind(&instance) {
-                init.call(interpreter, arguments, None)?;
-            }
+        let instance = Object::instance(klass);
+        if let Some(Object::Func(initializer)) = self.find_method(Symbol::INIT) {
+            let (slots, rest) = initializer.positional(arguments);
+            initializer.call_method(interpreter, &instance, slots, rest)?;
         }
         Ok(instance)
     }
 
-    pub fn find_method(&self, name: &str) -> Option<Object> {
-        if let Some(method) = self.methods.get(name) {
+    pub fn find_method(&self, name: Symbol) -> Option<Object> {
+        if let Some(method) = self.methods.get(&name) {
             Some(method.clone())
         } else if let Some(superclass) = &self.superclass {
             superclass.find_method(name)
@@ -52,6 +62,116 @@ impl LoxClass {
             None
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn superclass(&self) -> Option<Rc<LoxClass>> {
+        self.superclass.clone()
+    }
+
+    pub fn is_subclass_of(&self, other: &Rc<LoxClass>) -> bool {
+        if std::ptr::eq(self, other.as_ref()) {
+            true
+        } else if let Some(superclass) = &self.superclass {
+            superclass.is_subclass_of(other)
+        } else {
+            false
+        }
+    }
+
+    // Names of all instance methods, including inherited ones, in sorted order
+    pub fn method_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.methods.keys().map(|name| name.to_string()).collect();
+        if let Some(superclass) = &self.superclass {
+            for name in superclass.method_names() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names.sort();
+        names
+    }
+
+    pub fn find_setter(&self, name: Symbol) -> Option<Object> {
+        if let Some(setter) = self.setters.get(&name) {
+            Some(setter.clone())
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_setter(name)
+        } else {
+            None
+        }
+    }
+
+    pub fn find_class_method(&self, name: Symbol) -> Option<Object> {
+        if let Some(method) = self.class_methods.get(&name) {
+            Some(method.clone())
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_class_method(name)
+        } else {
+            None
+        }
+    }
+
+    // Class fields are inherited like class methods. Assigning one through a
+    // subclass gives the subclass its own field and leaves the superclass's
+    // alone.
+    fn find_field(&self, name: Symbol) -> Option<Object> {
+        let field = self.fields.borrow().get(&name).cloned();
+        match (field, &self.superclass) {
+            (Some(value), _) => Some(value),
+            (None, Some(superclass)) => superclass.find_field(name),
+            (None, None) => None,
+        }
+    }
+
+    pub fn get(
+        &self,
+        name: &Token,
+        this: &Rc<LoxClass>,
+        interpreter: &Interpreter,
+    ) -> Result<Object, LoxResult> {
+        if let Some(value) = self.find_field(name.lexeme()) {
+            Ok(value)
+        } else if let Some(method) = self.find_class_method(name.lexeme()) {
+            if let Object::Func(func) = method {
+                func.bind_property(&Object::Class(Rc::clone(this)), interpreter)
+            } else {
+                panic!("tried to bind 'this' to a non-function {method:?}");
+            }
+        } else {
+            Err(LoxResult::runtime_error(
+                name,
+                &format!("Undefined property '{}'.", name.as_string()),
+            ))
+        }
+    }
+
+    pub fn set(&self, name: &Token, value: Object) {
+        self.fields.borrow_mut().insert(name.lexeme(), value);
+    }
+
+    pub fn clear_fields(&self) {
+        if let Ok(mut fields) = self.fields.try_borrow_mut() {
+            fields.clear();
+        }
+    }
+}
+
+impl Trace for LoxClass {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        if let Some(superclass) = &self.superclass {
+            edges.push(Node::Class(Rc::clone(superclass)));
+        }
+        for table in [&self.methods, &self.class_methods, &self.setters] {
+            edges.extend(table.values().filter_map(Node::of));
+        }
+        if let Ok(fields) = self.fields.try_borrow() {
+            edges.extend(fields.values().filter_map(Node::of));
+        }
+    }
 }
 
 impl fmt::Display for LoxClass {
@@ -70,11 +190,32 @@ impl LoxCallable for LoxClass {
         self.instantiate(interpreter, arguments, klass.unwrap())
     }
 
-    fn arity(&self) -> usize {
-        if let Some(Object::Func(initializer)) = self.find_method("init") {
+    fn call_named(
+        &self,
+        interpreter: &Interpreter,
+        arguments: Vec<Object>,
+        named: Vec<(Token, Object)>,
+        klass: Option<Rc<LoxClass>>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        let instance = Object::instance(klass.unwrap());
+        if let Some(Object::Func(initializer)) = self.find_method(Symbol::INIT) {
+            let (slots, rest) = initializer.bind_arguments(arguments, named, paren)?;
+            initializer.call_method(interpreter, &instance, slots, rest)?;
+        } else if let Some((name, _)) = named.first() {
+            return Err(LoxResult::runtime_error(
+                name,
+                &format!("Unknown argument '{}'.", name.as_string()),
+            ));
+        }
+        Ok(instance)
+    }
+
+    fn arity(&self) -> Arity {
+        if let Some(Object::Func(initializer)) = self.find_method(Symbol::INIT) {
             initializer.arity()
         } else {
-            0
+            Arity::exact(0)
         }
     }
 }
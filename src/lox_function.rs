@@ -1,22 +1,44 @@
 use std::cell::RefCell;
 use std::fmt;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 
 use crate::callable::*;
 use crate::environment::*;
 use crate::error::*;
+use crate::expr::*;
+use crate::gc::*;
 use crate::interpreter::*;
 use crate::lox_class::*;
 use crate::object::*;
 use crate::stmt::*;
+use crate::symbol::*;
 use crate::token::*;
+use crate::vm::*;
+
+// What runs when the function is called: statements walked by the
+// interpreter, or a closure compiled for the VM. Bound methods remember
+// their receiver in the environment or alongside the closure.
+#[derive(Clone)]
+pub enum Body {
+    Tree {
+        statements: Rc<Vec<Rc<Stmt>>>,
+        closure: Rc<RefCell<Environment>>,
+    },
+    Compiled {
+        closure: Rc<Closure>,
+        receiver: Option<Object>,
+        vm: Weak<Vm>,
+    },
+}
 
 pub struct LoxFunction {
     name: Token,
     is_initializer: bool,
+    is_getter: bool,
     params: Rc<Vec<Token>>,
-    body: Rc<Vec<Rc<Stmt>>>,
-    closure: Rc<RefCell<Environment>>,
+    defaults: Rc<Vec<Option<Rc<Expr>>>>,
+    rest: Option<Token>,
+    body: Body,
 }
 
 impl fmt::Debug for LoxFunction {
@@ -30,67 +52,198 @@ impl Clone for LoxFunction {
         Self {
             name: self.name.dup(),
             is_initializer: self.is_initializer,
+            is_getter: self.is_getter,
             params: Rc::clone(&self.params),
-            body: Rc::clone(&self.body),
-            closure: Rc::clone(&self.closure),
+            defaults: Rc::clone(&self.defaults),
+            rest: self.rest.as_ref().map(Token::dup),
+            body: self.body.clone(),
         }
     }
 }
 
-impl PartialEq for LoxFunction {
-    fn eq(&self, other: &Self) -> bool {
-        self.name.token_type() == other.name.token_type()
-            && Rc::ptr_eq(&self.params, &other.params)
-            && Rc::ptr_eq(&self.body, &other.body)
-            && Rc::ptr_eq(&self.closure, &other.closure)
-    }
-}
-
 impl LoxFunction {
     pub fn new(
         declaration: &FunctionStmt,
         closure: &Rc<RefCell<Environment>>,
         is_initializer: bool,
     ) -> Self {
+        // Only environments a function closes over can be part of a cycle
+        track(Node::Environment(Rc::clone(closure)));
         Self {
             name: declaration.name.dup(),
             is_initializer,
+            is_getter: declaration.is_getter,
             params: Rc::clone(&declaration.params),
-            body: Rc::clone(&declaration.body),
-            closure: Rc::clone(closure),
+            defaults: Rc::clone(&declaration.defaults),
+            rest: declaration.rest.as_ref().map(Token::dup),
+            body: Body::Tree {
+                statements: Rc::clone(&declaration.body),
+                closure: Rc::clone(closure),
+            },
         }
     }
 
+    pub fn compiled(closure: Rc<Closure>, vm: Weak<Vm>) -> Self {
+        let function = Rc::clone(&closure.function);
+        Self {
+            name: function.name.dup(),
+            is_initializer: function.is_initializer,
+            is_getter: function.is_getter,
+            params: Rc::clone(&function.params),
+            defaults: Rc::clone(&function.defaults),
+            rest: function.rest.as_ref().map(Token::dup),
+            body: Body::Compiled {
+                closure,
+                receiver: None,
+                vm,
+            },
+        }
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub fn is_getter(&self) -> bool {
+        self.is_getter
+    }
+
     pub fn bind(&self, instance: &Object) -> Object {
-        let environment = RefCell::new(Environment::new_with_enclosing(Rc::clone(&self.closure)));
-        environment.borrow_mut().define("this", instance.clone());
+        let body = match &self.body {
+            Body::Tree {
+                statements,
+                closure,
+            } => {
+                let mut environment = Environment::new_with_enclosing(Rc::clone(closure));
+                environment.define(Symbol::THIS, instance.clone());
+                Body::Tree {
+                    statements: Rc::clone(statements),
+                    closure: Rc::new(RefCell::new(environment)),
+                }
+            }
+            Body::Compiled { closure, vm, .. } => Body::Compiled {
+                closure: Rc::clone(closure),
+                receiver: Some(instance.clone()),
+                vm: vm.clone(),
+            },
+        };
         Object::Func(Rc::new(Self {
             name: self.name.dup(),
             is_initializer: self.is_initializer,
+            is_getter: self.is_getter,
             params: Rc::clone(&self.params),
-            body: Rc::clone(&self.body),
-            closure: Rc::new(environment),
+            defaults: Rc::clone(&self.defaults),
+            rest: self.rest.as_ref().map(Token::dup),
+            body,
         }))
     }
+
+    // Binds the method to `instance`, running it right away if it is a getter
+    pub fn bind_property(
+        &self,
+        instance: &Object,
+        interpreter: &Interpreter,
+    ) -> Result<Object, LoxResult> {
+        let method = self.bind(instance);
+        if self.is_getter {
+            if let Object::Func(getter) = &method {
+                return getter.call(interpreter, Vec::new(), None);
+            }
+        }
+        Ok(method)
+    }
+
+    // Calls the method with `this` set to `receiver`, as calling what bind()
+    // returns would, without making the bound function first
+    pub fn call_method(
+        &self,
+        interpreter: &Interpreter,
+        receiver: &Object,
+        slots: Vec<Option<Object>>,
+        rest: Vec<Object>,
+    ) -> Result<Object, LoxResult> {
+        match &self.body {
+            Body::Tree {
+                statements,
+                closure,
+            } => {
+                let mut environment = Environment::new_with_enclosing(Rc::clone(closure));
+                environment.define(Symbol::THIS, receiver.clone());
+                let closure = Rc::new(RefCell::new(environment));
+                let result = interpreter.profile_call(self.name.lexeme(), self.name.line, || {
+                    self.run_tree(interpreter, statements, &closure, slots, rest)
+                });
+                finish_tail_calls(interpreter, result)
+            }
+            Body::Compiled { closure, vm, .. } => {
+                let vm = vm.upgrade().expect("function outlived its VM");
+                vm.call_function(closure, Some(receiver.clone()), slots, rest)
+            }
+        }
+    }
+
+    // Splits positional arguments into one slot per parameter and the rest
+    #[inline(never)]
+    pub fn positional(&self, arguments: Vec<Object>) -> (Vec<Option<Object>>, Vec<Object>) {
+        let mut arguments = arguments.into_iter();
+        let slots = arguments
+            .by_ref()
+            .take(self.params.len())
+            .map(Some)
+            .collect();
+        (slots, arguments.collect())
+    }
 }
 
-impl LoxCallable for LoxFunction {
-    fn call(
+impl LoxFunction {
+    fn invoke(
         &self,
         interpreter: &Interpreter,
-        arguments: Vec<Object>,
-        _klass: Option<Rc<LoxClass>>,
+        slots: Vec<Option<Object>>,
+        rest: Vec<Object>,
     ) -> Result<Object, LoxResult> {
-        let mut e = Environment::new_with_enclosing(Rc::clone(&self.closure));
+        let result = self.run(interpreter, slots, rest);
+        finish_tail_calls(interpreter, result)
+    }
 
-        for (param, arg) in self.params.iter().zip(arguments.iter()) {
-            e.define(&param.as_string(), arg.clone());
+    fn run(
+        &self,
+        interpreter: &Interpreter,
+        slots: Vec<Option<Object>>,
+        rest: Vec<Object>,
+    ) -> Result<Object, LoxResult> {
+        match &self.body {
+            Body::Tree {
+                statements,
+                closure,
+            } => interpreter.profile_call(self.name.lexeme(), self.name.line, || {
+                self.run_tree(interpreter, statements, closure, slots, rest)
+            }),
+            Body::Compiled {
+                closure,
+                receiver,
+                vm,
+            } => {
+                let vm = vm.upgrade().expect("function outlived its VM");
+                vm.call_function(closure, receiver.clone(), slots, rest)
+            }
         }
+    }
 
-        match interpreter.execute_block(&self.body, e) {
+    // Runs the body with one slot per declared parameter
+    fn run_tree(
+        &self,
+        interpreter: &Interpreter,
+        statements: &Rc<Vec<Rc<Stmt>>>,
+        closure: &Rc<RefCell<Environment>>,
+        slots: Vec<Option<Object>>,
+        rest: Vec<Object>,
+    ) -> Result<Object, LoxResult> {
+        let e = self.parameters(interpreter, closure, slots, rest)?;
+        match interpreter.execute_block(statements, e) {
             Err(LoxResult::ReturnValue { value }) => {
                 if self.is_initializer {
-                    self.closure.borrow().get_at(0, "this")
+                    Ok(closure.borrow().get_at(Slot { depth: 0, index: 0 }))
                 } else {
                     Ok(value)
                 }
@@ -98,7 +251,7 @@ impl LoxCallable for LoxFunction {
             Err(e) => Err(e),
             Ok(_) => {
                 if self.is_initializer {
-                    self.closure.borrow().get_at(0, "this")
+                    Ok(closure.borrow().get_at(Slot { depth: 0, index: 0 }))
                 } else {
                     Ok(Object::Nil)
                 }
@@ -106,13 +259,169 @@ impl LoxCallable for LoxFunction {
         }
     }
 
-    fn arity(&self) -> usize {
-        self.params.len()
+    // The environment the body runs in. Missing slots take their defaults,
+    // which are evaluated in the function's own scope so they can refer to
+    // earlier parameters. Kept out of line, like positional(), so the frames
+    // a call leaves on the stack while its body runs stay small.
+    #[inline(never)]
+    fn parameters(
+        &self,
+        interpreter: &Interpreter,
+        closure: &Rc<RefCell<Environment>>,
+        mut slots: Vec<Option<Object>>,
+        rest: Vec<Object>,
+    ) -> Result<Rc<RefCell<Environment>>, LoxResult> {
+        slots.resize(self.params.len(), None);
+        let e = Rc::new(RefCell::new(Environment::new_with_enclosing(Rc::clone(
+            closure,
+        ))));
+
+        for ((param, default), slot) in self.params.iter().zip(self.defaults.iter()).zip(slots) {
+            let value = match (slot, default) {
+                (Some(arg), _) => arg,
+                (None, Some(default)) => interpreter.evaluate_in(default, &e)?,
+                (None, None) => Object::Nil,
+            };
+            e.borrow_mut().define(param.lexeme(), value);
+        }
+
+        if let Some(name) = &self.rest {
+            e.borrow_mut().define(name.lexeme(), Object::list(rest));
+        }
+        Ok(e)
+    }
+
+    // Matches the arguments of a call to the parameters: one slot per
+    // parameter, empty where its default applies, and the extra positional
+    // arguments for the rest parameter
+    pub fn bind_arguments(
+        &self,
+        arguments: Vec<Object>,
+        named: Vec<(Token, Object)>,
+        paren: &Token,
+    ) -> Result<(Vec<Option<Object>>, Vec<Object>), LoxResult> {
+        if arguments.len() > self.params.len() && self.rest.is_none() {
+            return Err(LoxResult::runtime_error(
+                paren,
+                &format!(
+                    "Expected {} arguments but got {}.",
+                    self.arity(),
+                    arguments.len() + named.len()
+                ),
+            ));
+        }
+
+        let mut arguments = arguments.into_iter();
+        let mut slots: Vec<Option<Object>> = arguments
+            .by_ref()
+            .take(self.params.len())
+            .map(Some)
+            .collect();
+        slots.resize(self.params.len(), None);
+
+        for (name, value) in named {
+            let position = self.params.iter().position(|p| p.lexeme() == name.lexeme());
+            match position {
+                Some(i) if slots[i].is_some() => {
+                    return Err(LoxResult::runtime_error(
+                        &name,
+                        &format!("Argument '{}' given more than once.", name.as_string()),
+                    ));
+                }
+                Some(i) => slots[i] = Some(value),
+                None => {
+                    return Err(LoxResult::runtime_error(
+                        &name,
+                        &format!("Unknown argument '{}'.", name.as_string()),
+                    ));
+                }
+            }
+        }
+
+        for ((param, default), slot) in self.params.iter().zip(self.defaults.iter()).zip(&slots) {
+            if slot.is_none() && default.is_none() {
+                return Err(LoxResult::runtime_error(
+                    paren,
+                    &format!("Missing argument for parameter '{}'.", param.as_string()),
+                ));
+            }
+        }
+
+        Ok((slots, arguments.collect()))
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn call(
+        &self,
+        interpreter: &Interpreter,
+        arguments: Vec<Object>,
+        _klass: Option<Rc<LoxClass>>,
+    ) -> Result<Object, LoxResult> {
+        let (slots, rest) = self.positional(arguments);
+        self.invoke(interpreter, slots, rest)
+    }
+
+    fn call_named(
+        &self,
+        interpreter: &Interpreter,
+        arguments: Vec<Object>,
+        named: Vec<(Token, Object)>,
+        _klass: Option<Rc<LoxClass>>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        let (slots, rest) = self.bind_arguments(arguments, named, paren)?;
+        self.invoke(interpreter, slots, rest)
     }
+
+    fn arity(&self) -> Arity {
+        Arity {
+            min: self.defaults.iter().take_while(|d| d.is_none()).count(),
+            max: self.params.len(),
+            variadic: self.rest.is_some(),
+        }
+    }
+}
+
+impl Trace for LoxFunction {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        match &self.body {
+            Body::Tree { closure, .. } => edges.push(Node::Environment(Rc::clone(closure))),
+            Body::Compiled {
+                closure, receiver, ..
+            } => {
+                edges.push(Node::Closure(Rc::clone(closure)));
+                edges.extend(receiver.as_ref().and_then(Node::of));
+            }
+        }
+    }
+}
+
+// Calls in tail position unwind to here and run in a loop, so tail recursion
+// doesn't grow the Rust stack. Kept out of line so ordinary calls don't
+// carry the loop's frame.
+#[inline(never)]
+fn finish_tail_calls(
+    interpreter: &Interpreter,
+    mut result: Result<Object, LoxResult>,
+) -> Result<Object, LoxResult> {
+    while let Err(LoxResult::TailCall {
+        function,
+        slots,
+        rest,
+    }) = result
+    {
+        result = function.run(interpreter, slots, rest);
+    }
+    result
 }
 
 impl fmt::Display for LoxFunction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "<fn {}>", self.name.as_string())
+        if self.is_getter {
+            write!(f, "<getter {}>", self.name.as_string())
+        } else {
+            write!(f, "<fn {}>", self.name.as_string())
+        }
     }
 }
@@ -1,18 +1,25 @@
-use std::cell::RefCell;
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::rc::Rc;
 
 use crate::error::*;
+use crate::gc::*;
+use crate::inline_cache::*;
+use crate::interpreter::*;
 use crate::lox_class::*;
+use crate::lox_function::*;
 use crate::object::*;
+use crate::symbol::*;
 use crate::token::*;
+use crate::token_type::*;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct LoxInstance {
     klass: Rc<LoxClass>,
-    fields: RefCell<HashMap<String, Object>>,
+    fields: RefCell<HashMap<Symbol, Object>>,
+    active_hooks: RefCell<HashSet<Symbol>>,
+    formatting: Cell<bool>,
 }
 
 impl LoxInstance {
@@ -20,18 +27,31 @@ impl LoxInstance {
         Self {
             klass: Rc::clone(&klass),
             fields: RefCell::new(HashMap::new()),
+            active_hooks: RefCell::new(HashSet::new()),
+            formatting: Cell::new(false),
         }
     }
 
-    pub fn get(&self, name: &Token, this: &Rc<LoxInstance>) -> Result<Object, LoxResult> {
-        if let Entry::Occupied(o) = self.fields.borrow_mut().entry(name.as_string()) {
-            Ok(o.get().clone())
-        } else if let Some(method) = self.klass.find_method(&name.as_string()) {
+    // `cache` is the inline cache of the expression doing the lookup, if any
+    pub fn get(
+        &self,
+        name: &Token,
+        cache: Option<&InlineCache>,
+        this: &Rc<LoxInstance>,
+        interpreter: &Interpreter,
+    ) -> Result<Object, LoxResult> {
+        let field = self.fields.borrow().get(&name.lexeme()).cloned();
+        if let Some(value) = field {
+            Ok(value)
+        } else if let Some(method) = self.find_method(name.lexeme(), cache) {
             if let Object::Func(func) = method {
-                Ok(func.bind(&Object::Instance(Rc::clone(this))))
+                func.bind_property(&Object::Instance(Rc::clone(this)), interpreter)
             } else {
                 panic!("tried to bind 'this' to a non-function {method:?}");
             }
+        } else if let Some(hook) = self.hook(name.lexeme(), "__get__") {
+            let arguments = vec![Object::Str(name.as_string())];
+            self.run_hook(name, hook, arguments, this, interpreter)
         } else {
             Err(LoxResult::runtime_error(
                 name,
@@ -40,13 +60,239 @@ impl LoxInstance {
         }
     }
 
-    pub fn set(&self, name: &Token, value: Object) {
-        self.fields.borrow_mut().insert(name.as_string(), value);
+    // The method a call of `name` runs when it can be called without binding
+    // it first: one no field shadows and that isn't a getter
+    pub fn method(&self, name: &Token, cache: &InlineCache) -> Option<Rc<LoxFunction>> {
+        if self.fields.borrow().contains_key(&name.lexeme()) {
+            return None;
+        }
+        match self.find_method(name.lexeme(), Some(cache)) {
+            Some(Object::Func(method)) if !method.is_getter() => Some(method),
+            _ => None,
+        }
+    }
+
+    pub fn klass(&self) -> &Rc<LoxClass> {
+        &self.klass
+    }
+
+    pub fn has_field(&self, name: &str) -> bool {
+        Symbol::lookup(name).is_some_and(|name| self.fields.borrow().contains_key(&name))
+    }
+
+    pub fn field_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .fields
+            .borrow()
+            .keys()
+            .map(|name| name.to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn has_property(&self, name: &str) -> bool {
+        Symbol::lookup(name).is_some_and(|name| {
+            self.fields.borrow().contains_key(&name) || self.klass.find_method(name).is_some()
+        })
+    }
+
+    // Assignments go through a setter or `__set__` when the class has one.
+    // While a hook runs for a property, writes to that same property on
+    // this instance go straight to the field so the hook can store it.
+    pub fn set(
+        &self,
+        name: &Token,
+        value: Object,
+        cache: Option<&InlineCache>,
+        this: &Rc<LoxInstance>,
+        interpreter: &Interpreter,
+    ) -> Result<(), LoxResult> {
+        let key = name.lexeme();
+        if !self.active_hooks.borrow().contains(&key) {
+            let setter = match cache {
+                Some(cache) => cache.lookup(&self.klass, || self.klass.find_setter(key)),
+                None => self.klass.find_setter(key),
+            };
+            if let Some(setter) = setter {
+                self.run_hook(name, setter, vec![value], this, interpreter)?;
+                return Ok(());
+            } else if let Some(hook) = self.hook(key, "__set__") {
+                let arguments = vec![Object::Str(key.to_string()), value];
+                self.run_hook(name, hook, arguments, this, interpreter)?;
+                return Ok(());
+            }
+        }
+
+        self.fields.borrow_mut().insert(key, value);
+        Ok(())
+    }
+
+    // Uses the class's toString() when it has one. A toString() that ends up
+    // stringifying its own instance gets the default representation instead.
+    pub fn stringify(
+        &self,
+        this: &Rc<LoxInstance>,
+        location: &Token,
+        interpreter: &Interpreter,
+    ) -> Result<String, LoxResult> {
+        if let Some(method) = self.hook(Symbol::TO_STRING, "toString") {
+            let name = Token::new(TokenType::Identifier, "toString", None, location.line);
+            match self.run_hook(&name, method, Vec::new(), this, interpreter)? {
+                Object::Str(s) => Ok(s),
+                _ => Err(LoxResult::runtime_error(
+                    location,
+                    "toString() must return a string.",
+                )),
+            }
+        } else {
+            self.describe(location, interpreter)
+        }
+    }
+
+    // The default representation, with the fields stringified the way print
+    // does it, so a nested instance uses its own toString(). An instance
+    // reached again while it is being described shows as {...}.
+    fn describe(&self, location: &Token, interpreter: &Interpreter) -> Result<String, LoxResult> {
+        let mut fields: Vec<(Symbol, Object)> = self
+            .fields
+            .borrow()
+            .iter()
+            .map(|(name, value)| (*name, value.clone()))
+            .collect();
+        let mut text = format!("{} instance", self.klass);
+        if fields.is_empty() {
+            return Ok(text);
+        }
+        if self.formatting.replace(true) {
+            return Ok(text + " {...}");
+        }
+
+        fields.sort_by_key(|(name, _)| name.as_str());
+        let result = fields
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, (name, value))| {
+                let separator = if i == 0 { " {" } else { "," };
+                let value = interpreter.stringify(value, location)?;
+                text.push_str(&format!("{separator} {name}: {value}"));
+                Ok(())
+            });
+        self.formatting.set(false);
+        result.map(|()| text + " }")
+    }
+
+    pub fn clear_fields(&self) {
+        if let Ok(mut fields) = self.fields.try_borrow_mut() {
+            fields.clear();
+        }
+    }
+
+    pub fn has_to_string(&self) -> bool {
+        self.klass.find_method(Symbol::TO_STRING).is_some()
+    }
+
+    fn find_method(&self, name: Symbol, cache: Option<&InlineCache>) -> Option<Object> {
+        match cache {
+            Some(cache) => cache.lookup(&self.klass, || self.klass.find_method(name)),
+            None => self.klass.find_method(name),
+        }
+    }
+
+    fn hook(&self, property: Symbol, hook: &str) -> Option<Object> {
+        if self.active_hooks.borrow().contains(&property) {
+            None
+        } else {
+            self.klass.find_method(Symbol::lookup(hook)?)
+        }
+    }
+
+    fn run_hook(
+        &self,
+        name: &Token,
+        hook: Object,
+        arguments: Vec<Object>,
+        this: &Rc<LoxInstance>,
+        interpreter: &Interpreter,
+    ) -> Result<Object, LoxResult> {
+        let method = if let Object::Func(func) = hook {
+            func.bind(&Object::Instance(Rc::clone(this)))
+        } else {
+            panic!("tried to bind 'this' to a non-function {hook:?}");
+        };
+
+        self.active_hooks.borrow_mut().insert(name.lexeme());
+        let result = interpreter.call(method, arguments, name);
+        self.active_hooks.borrow_mut().remove(&name.lexeme());
+        result
+    }
+}
+
+impl Trace for LoxInstance {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        edges.push(Node::Class(Rc::clone(&self.klass)));
+        if let Ok(fields) = self.fields.try_borrow() {
+            edges.extend(fields.values().filter_map(Node::of));
+        }
     }
 }
 
 impl fmt::Display for LoxInstance {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} instance", self.klass)
+        write!(f, "{} instance", self.klass)?;
+
+        let fields = self.fields.borrow();
+        if fields.is_empty() {
+            return Ok(());
+        }
+        if self.formatting.replace(true) {
+            return write!(f, " {{...}}");
+        }
+
+        let mut names: Vec<&Symbol> = fields.keys().collect();
+        names.sort_by_key(|name| name.as_str());
+        // Written in one go so the flag is cleared even when a write fails
+        let result = (|| {
+            write!(f, " {{")?;
+            for (i, name) in names.iter().enumerate() {
+                let separator = if i == 0 { "" } else { "," };
+                write!(f, "{separator} {name}: {}", fields[*name])?;
+            }
+            write!(f, " }}")
+        })();
+        self.formatting.set(false);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    // Fails once the fields start
+    struct Failing;
+
+    impl Write for Failing {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if s.contains('{') {
+                Err(fmt::Error)
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn failed_write_does_not_leave_instance_formatting() {
+        let klass = LoxClass::new("A", None, HashMap::new(), HashMap::new(), HashMap::new());
+        let instance = LoxInstance::new(Rc::new(klass));
+        instance
+            .fields
+            .borrow_mut()
+            .insert(Symbol::intern("x"), Object::Num(1.0));
+
+        assert!(write!(Failing, "{instance}").is_err());
+        assert_eq!(instance.to_string(), "A instance { x: 1 }");
     }
 }
@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::gc::*;
+use crate::object::*;
+use crate::symbol::*;
+
+#[derive(Debug, Clone)]
+pub struct LoxTrait {
+    name: String,
+    methods: HashMap<Symbol, Object>,
+}
+
+impl LoxTrait {
+    pub fn new(name: &str, methods: HashMap<Symbol, Object>) -> Self {
+        Self {
+            name: name.to_string(),
+            methods,
+        }
+    }
+
+    pub fn methods(&self) -> &HashMap<Symbol, Object> {
+        &self.methods
+    }
+}
+
+impl Trace for LoxTrait {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        edges.extend(self.methods.values().filter_map(Node::of));
+    }
+}
+
+impl fmt::Display for LoxTrait {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
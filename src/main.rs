@@ -1,16 +1,35 @@
 use std::env::args;
 use std::io::{self, stdout, BufRead, Write};
+use std::path::Path;
 use std::rc::Rc;
 
+#[cfg(feature = "count-allocs")]
+mod allocations;
+
+mod ast_printer;
+use ast_printer::*;
+
 mod callable;
+mod chunk;
+mod compiler;
 mod environment;
 mod expr;
+mod gc;
+mod inline_cache;
 mod lox_class;
 mod lox_function;
 mod lox_instance;
+mod lox_trait;
 mod native_functions;
 mod object;
+
+mod optimizer;
+use optimizer::*;
+
+mod pattern;
+mod profiler;
 mod stmt;
+mod symbol;
 
 mod resolver;
 use resolver::*;
@@ -30,40 +49,103 @@ use scanner::*;
 mod token;
 mod token_type;
 
+mod vm;
+use vm::*;
+
 pub fn main() {
-    let args: Vec<String> = args().collect();
-    let lox = Lox::new();
+    let mut options = Options::default();
+    let mut args: Vec<String> = args().skip(1).collect();
+    while args.first().is_some_and(|arg| arg.starts_with("--")) {
+        match args.remove(0).as_str() {
+            "--vm" => options.use_vm = true,
+            "--optimize" => options.optimize = true,
+            "--dump-ast" => options.dump_ast = true,
+            "--profile" => options.profile = true,
+            _ => usage(),
+        }
+    }
+    // Only the tree-walker is instrumented, and the profile is written when
+    // a script finishes
+    if options.profile && (options.use_vm || args.len() != 1) {
+        usage();
+    }
+    let lox = Lox::new(options);
 
     match args.len() {
-        1 => lox.run_prompt(),
-        2 => lox.run_file(&args[1]).expect("Could not run file"),
-        _ => {
-            println!("Usage: lox-ast [script]");
-            std::process::exit(64);
-        }
+        0 => lox.run_prompt(),
+        1 => lox.run_file(&args[0]).expect("Could not run file"),
+        _ => usage(),
     }
 }
 
+fn usage() -> ! {
+    println!("Usage: lox-ast [--vm] [--optimize] [--dump-ast] [script]");
+    println!("       lox-ast --profile [--optimize] script");
+    std::process::exit(64);
+}
+
+#[derive(Default)]
+struct Options {
+    use_vm: bool,
+    // Fold constants and drop dead code before running
+    optimize: bool,
+    // Print the tree that would run instead of running it
+    dump_ast: bool,
+    // Time functions and lines, and report them when the script ends
+    profile: bool,
+}
+
+// Both backends share the scanner, parser, resolver and optimizer
+enum Backend {
+    Tree(Interpreter),
+    Vm(Rc<Vm>),
+}
+
 struct Lox {
-    interpreter: Interpreter,
+    backend: Backend,
+    options: Options,
 }
 
 impl Lox {
-    pub fn new() -> Lox {
-        Lox {
-            interpreter: Interpreter::new(),
-        }
+    pub fn new(options: Options) -> Lox {
+        let backend = if options.use_vm {
+            Backend::Vm(Vm::new())
+        } else if options.profile {
+            Backend::Tree(Interpreter::new_with_profiler())
+        } else {
+            Backend::Tree(Interpreter::new())
+        };
+        Lox { backend, options }
     }
 
     pub fn run_file(&self, path: &str) -> io::Result<()> {
         let buf = std::fs::read_to_string(path)?;
-        match self.run(buf) {
+        let result = self.run(buf);
+        self.write_profile(path)?;
+        match result {
             Ok(_) => std::process::exit(0),
             Err(LoxResult::RuntimeError { .. }) => std::process::exit(70),
             _ => std::process::exit(65),
         }
     }
 
+    // Prints the profile to stderr and writes its folded stacks, for
+    // flamegraph tools, to the current directory as <script>.folded
+    fn write_profile(&self, path: &str) -> io::Result<()> {
+        let Backend::Tree(interpreter) = &self.backend else {
+            return Ok(());
+        };
+        if let Some(profiler) = interpreter.profiler() {
+            let profiler = profiler.borrow();
+            let stem = Path::new(path).file_stem().and_then(|s| s.to_str());
+            let folded = format!("{}.folded", stem.unwrap_or("lox"));
+            std::fs::write(&folded, profiler.folded())?;
+            eprint!("\n{}", profiler.report());
+            eprintln!("\nFolded stacks written to {folded}");
+        }
+        Ok(())
+    }
+
     pub fn run_prompt(&self) {
         let stdin = io::stdin();
         print!("> ");
@@ -84,25 +166,41 @@ impl Lox {
 
     fn run(&self, source: String) -> Result<(), LoxResult> {
         if source == "@" {
-            self.interpreter.print_environment();
+            match &self.backend {
+                Backend::Tree(interpreter) => interpreter.print_environment(),
+                Backend::Vm(vm) => vm.print_environment(),
+            }
             return Ok(());
         }
         let mut scanner = Scanner::new(source);
         let tokens = scanner.scan_tokens()?;
 
         let mut parser = Parser::new(tokens);
-        let statements = parser.parse()?;
+        let parsed = parser.parse();
+        if !parsed.success() {
+            return Err(LoxResult::fail());
+        }
 
-        let resolver = Resolver::new(&self.interpreter);
-        let s = Rc::new(statements);
+        let resolver = Resolver::new();
+        let mut s = Rc::new(parsed.statements);
         resolver.resolve(&Rc::clone(&s))?;
-
-        if resolver.success() {
-            self.interpreter.interpret(&Rc::clone(&s))?;
-        } else {
+        if !resolver.success() {
             std::process::exit(65);
         }
 
+        if self.options.optimize {
+            s = Rc::new(Optimizer::new().optimize(&s)?);
+        }
+        if self.options.dump_ast {
+            println!("{}", AstPrinter.print(&s)?);
+            return Ok(());
+        }
+
+        match &self.backend {
+            Backend::Tree(interpreter) => interpreter.interpret(&s)?,
+            Backend::Vm(vm) => vm.interpret(&s)?,
+        }
+
         Ok(())
     }
 }
@@ -2,23 +2,49 @@ use std::fmt;
 use std::rc::Rc;
 use std::time::SystemTime;
 
+#[cfg(feature = "count-allocs")]
+use crate::allocations::*;
 use crate::callable::*;
+use crate::environment::*;
 use crate::error::*;
+use crate::gc::*;
 use crate::interpreter::*;
-use crate::lox_class::*;
 use crate::object::*;
+use crate::symbol::*;
+use crate::token::*;
+
+// A function written in Rust. It gets the call's closing paren, so the
+// errors it raises point at the call.
+pub trait NativeFunction {
+    fn call(
+        &self,
+        interpreter: &Interpreter,
+        args: Vec<Object>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult>;
+    fn arity(&self) -> Arity;
+}
 
 #[derive(Clone)]
 pub struct LoxNative {
-    pub func: Rc<dyn LoxCallable>,
+    pub name: Symbol,
+    pub func: Rc<dyn NativeFunction>,
 }
 
-impl PartialEq for LoxNative {
-    fn eq(&self, other: &Self) -> bool {
-        std::ptr::eq(
-            Rc::as_ptr(&self.func) as *const (),
-            Rc::as_ptr(&other.func) as *const (),
-        )
+impl LoxNative {
+    pub fn call(
+        &self,
+        interpreter: &Interpreter,
+        arguments: Vec<Object>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        interpreter.profile_call(self.name, 0, || {
+            self.func.call(interpreter, arguments, paren)
+        })
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.func.arity()
     }
 }
 
@@ -36,12 +62,12 @@ impl fmt::Display for LoxNative {
 
 pub struct NativeClock;
 
-impl LoxCallable for NativeClock {
+impl NativeFunction for NativeClock {
     fn call(
         &self,
         _terp: &Interpreter,
         _args: Vec<Object>,
-        _klass: Option<Rc<LoxClass>>,
+        _paren: &Token,
     ) -> Result<Object, LoxResult> {
         match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
             Ok(n) => Ok(Object::Num(n.as_millis() as f64)),
@@ -52,7 +78,229 @@ impl LoxCallable for NativeClock {
         }
     }
 
-    fn arity(&self) -> usize {
-        0
+    fn arity(&self) -> Arity {
+        Arity::exact(0)
+    }
+}
+
+pub fn define_natives(globals: &mut Environment) {
+    let natives: [(&str, Rc<dyn NativeFunction>); 9] = [
+        ("clock", Rc::new(NativeClock {})),
+        ("classOf", Rc::new(NativeClassOf {})),
+        ("className", Rc::new(NativeClassName {})),
+        ("superclassOf", Rc::new(NativeSuperclassOf {})),
+        ("fields", Rc::new(NativeFields {})),
+        ("methods", Rc::new(NativeMethods {})),
+        ("hasField", Rc::new(NativeHasField {})),
+        ("gc", Rc::new(NativeGc {})),
+        ("gcStats", Rc::new(NativeGcStats {})),
+    ];
+    #[cfg(feature = "count-allocs")]
+    let natives = natives.into_iter().chain([(
+        "allocations",
+        Rc::new(NativeAllocations {}) as Rc<dyn NativeFunction>,
+    )]);
+
+    for (name, func) in natives {
+        let name = Symbol::intern(name);
+        globals.define(name, Object::Native(Rc::new(LoxNative { name, func })));
+    }
+}
+
+pub struct NativeClassOf;
+
+impl NativeFunction for NativeClassOf {
+    fn call(
+        &self,
+        _terp: &Interpreter,
+        args: Vec<Object>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        match &args[0] {
+            Object::Instance(inst) => Ok(Object::Class(Rc::clone(inst.klass()))),
+            _ => Err(LoxResult::runtime_error(
+                paren,
+                "Argument to classOf() must be an instance.",
+            )),
+        }
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(1)
+    }
+}
+
+pub struct NativeClassName;
+
+impl NativeFunction for NativeClassName {
+    fn call(
+        &self,
+        _terp: &Interpreter,
+        args: Vec<Object>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        match &args[0] {
+            Object::Class(c) => Ok(Object::Str(c.name().to_string())),
+            _ => Err(LoxResult::runtime_error(
+                paren,
+                "Argument to className() must be a class.",
+            )),
+        }
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(1)
+    }
+}
+
+pub struct NativeSuperclassOf;
+
+impl NativeFunction for NativeSuperclassOf {
+    fn call(
+        &self,
+        _terp: &Interpreter,
+        args: Vec<Object>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        match &args[0] {
+            Object::Class(c) => Ok(c.superclass().map_or(Object::Nil, Object::Class)),
+            _ => Err(LoxResult::runtime_error(
+                paren,
+                "Argument to superclassOf() must be a class.",
+            )),
+        }
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(1)
+    }
+}
+
+pub struct NativeFields;
+
+impl NativeFunction for NativeFields {
+    fn call(
+        &self,
+        _terp: &Interpreter,
+        args: Vec<Object>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        match &args[0] {
+            Object::Instance(inst) => Ok(Object::list(
+                inst.field_names().into_iter().map(Object::Str).collect(),
+            )),
+            _ => Err(LoxResult::runtime_error(
+                paren,
+                "Argument to fields() must be an instance.",
+            )),
+        }
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(1)
+    }
+}
+
+pub struct NativeMethods;
+
+impl NativeFunction for NativeMethods {
+    fn call(
+        &self,
+        _terp: &Interpreter,
+        args: Vec<Object>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        match &args[0] {
+            Object::Class(c) => Ok(Object::list(
+                c.method_names().into_iter().map(Object::Str).collect(),
+            )),
+            _ => Err(LoxResult::runtime_error(
+                paren,
+                "Argument to methods() must be a class.",
+            )),
+        }
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(1)
+    }
+}
+
+pub struct NativeHasField;
+
+impl NativeFunction for NativeHasField {
+    fn call(
+        &self,
+        _terp: &Interpreter,
+        args: Vec<Object>,
+        paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        match (&args[0], &args[1]) {
+            (Object::Instance(inst), Object::Str(name)) => Ok(Object::Bool(inst.has_field(name))),
+            _ => Err(LoxResult::runtime_error(
+                paren,
+                "Arguments to hasField() must be an instance and a string.",
+            )),
+        }
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(2)
+    }
+}
+
+// Runs the cycle collector and returns how many values it freed
+pub struct NativeGc;
+
+impl NativeFunction for NativeGc {
+    fn call(
+        &self,
+        _terp: &Interpreter,
+        _args: Vec<Object>,
+        _paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        Ok(Object::Num(collect() as f64))
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(0)
+    }
+}
+
+// Describes the heap as a string: live values by kind, then how many
+// collections have run and how many values they freed
+pub struct NativeGcStats;
+
+impl NativeFunction for NativeGcStats {
+    fn call(
+        &self,
+        _terp: &Interpreter,
+        _args: Vec<Object>,
+        _paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        Ok(Object::Str(stats().to_string()))
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(0)
+    }
+}
+
+#[cfg(feature = "count-allocs")]
+pub struct NativeAllocations;
+
+#[cfg(feature = "count-allocs")]
+impl NativeFunction for NativeAllocations {
+    fn call(
+        &self,
+        _terp: &Interpreter,
+        _args: Vec<Object>,
+        _paren: &Token,
+    ) -> Result<Object, LoxResult> {
+        Ok(Object::Num(allocations() as f64))
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(0)
     }
 }
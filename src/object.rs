@@ -1,13 +1,16 @@
+use std::cell::RefCell;
 use std::cmp::*;
 use std::fmt;
 use std::rc::Rc;
 
+use crate::gc::*;
 use crate::lox_class::*;
 use crate::lox_function::*;
 use crate::lox_instance::*;
+use crate::lox_trait::*;
 use crate::native_functions::*;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Object {
     Num(f64),
     Str(String),
@@ -15,12 +18,65 @@ pub enum Object {
     Func(Rc<LoxFunction>),
     Class(Rc<LoxClass>),
     Instance(Rc<LoxInstance>),
+    List(Rc<RefCell<Vec<Object>>>),
+    Trait(Rc<LoxTrait>),
     Native(Rc<LoxNative>),
     Nil,
     ArithmeticError,
     NumsOrStringsError,
 }
 
+impl Object {
+    pub fn list(items: Vec<Object>) -> Object {
+        let list = Rc::new(RefCell::new(items));
+        track(Node::List(Rc::clone(&list)));
+        Object::List(list)
+    }
+
+    pub fn class(klass: LoxClass) -> Object {
+        let klass = Rc::new(klass);
+        track(Node::Class(Rc::clone(&klass)));
+        Object::Class(klass)
+    }
+
+    pub fn instance(klass: Rc<LoxClass>) -> Object {
+        let instance = Rc::new(LoxInstance::new(klass));
+        track(Node::Instance(Rc::clone(&instance)));
+        Object::Instance(instance)
+    }
+
+    // Lox equality, shared by '==', '!=' and match patterns. Numbers, strings,
+    // booleans and nil compare by value (numbers follow IEEE 754, so NaN is
+    // never equal to itself). Functions, natives, classes, traits, instances
+    // and lists compare by identity. Values of different kinds are never equal.
+    pub fn is_equal(&self, other: &Object) -> bool {
+        match (self, other) {
+            (Object::Num(a), Object::Num(b)) => a == b,
+            (Object::Str(a), Object::Str(b)) => a == b,
+            (Object::Bool(a), Object::Bool(b)) => a == b,
+            (Object::Nil, Object::Nil) => true,
+            (Object::Func(a), Object::Func(b)) => Rc::ptr_eq(a, b),
+            (Object::Native(a), Object::Native(b)) => Rc::ptr_eq(a, b),
+            (Object::Class(a), Object::Class(b)) => Rc::ptr_eq(a, b),
+            (Object::Trait(a), Object::Trait(b)) => Rc::ptr_eq(a, b),
+            (Object::Instance(a), Object::Instance(b)) => Rc::ptr_eq(a, b),
+            (Object::List(a), Object::List(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    // Only nil and false are falsey
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Object::Nil | Object::Bool(false))
+    }
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_equal(other)
+    }
+}
+
 impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -36,9 +92,111 @@ impl fmt::Display for Object {
             Object::Func(func) => write!(f, "{func}"),
             Object::Class(c) => write!(f, "{c}"),
             Object::Instance(i) => write!(f, "{i}"),
+            Object::Trait(t) => write!(f, "{t}"),
+            Object::List(list) => {
+                write!(f, "[")?;
+                for (i, item) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
             Object::Native(n) => write!(f, "{n}"),
             Object::Nil => write!(f, "nil"),
             _ => panic!("Should not be trying to print this"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::*;
+    use crate::stmt::*;
+    use crate::symbol::*;
+    use crate::token::*;
+    use crate::token_type::*;
+    use std::collections::HashMap;
+
+    fn class(name: &str) -> Rc<LoxClass> {
+        Rc::new(LoxClass::new(
+            name,
+            None,
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        ))
+    }
+
+    fn function(name: &str) -> Object {
+        let declaration = FunctionStmt {
+            name: Token::new(TokenType::Identifier, name, None, 0),
+            params: Rc::new(Vec::new()),
+            defaults: Rc::new(Vec::new()),
+            rest: None,
+            body: Rc::new(Vec::new()),
+            is_getter: false,
+        };
+        let closure = Rc::new(RefCell::new(Environment::new()));
+        Object::Func(Rc::new(LoxFunction::new(&declaration, &closure, false)))
+    }
+
+    fn native() -> Object {
+        Object::Native(Rc::new(LoxNative {
+            name: Symbol::intern("clock"),
+            func: Rc::new(NativeClock {}),
+        }))
+    }
+
+    #[test]
+    fn equality_conformance() {
+        let point = class("Point");
+        let a = Object::Instance(Rc::new(LoxInstance::new(Rc::clone(&point))));
+        let b = Object::Instance(Rc::new(LoxInstance::new(Rc::clone(&point))));
+        let f = function("f");
+        let g = function("f");
+        let n = native();
+        let m = native();
+        let list = Object::list(vec![Object::Num(1.0)]);
+        let same_list = Object::list(vec![Object::Num(1.0)]);
+        let str = |s: &str| Object::Str(s.to_string());
+
+        let table: Vec<(Object, Object, bool)> = vec![
+            (Object::Num(1.0), Object::Num(1.0), true),
+            (Object::Num(1.0), Object::Num(2.0), false),
+            (Object::Num(0.0), Object::Num(-0.0), true),
+            (Object::Num(f64::NAN), Object::Num(f64::NAN), false),
+            (str("a"), str("a"), true),
+            (str("a"), str("b"), false),
+            (str("1"), Object::Num(1.0), false),
+            (Object::Bool(true), Object::Bool(true), true),
+            (Object::Bool(true), Object::Bool(false), false),
+            (Object::Bool(false), Object::Nil, false),
+            (Object::Nil, Object::Nil, true),
+            (Object::Nil, Object::Num(0.0), false),
+            (str(""), Object::Nil, false),
+            (a.clone(), a.clone(), true),
+            (a.clone(), b.clone(), false),
+            (a.clone(), Object::Nil, false),
+            (f.clone(), f.clone(), true),
+            (f.clone(), g.clone(), false),
+            (n.clone(), n.clone(), true),
+            (n.clone(), m.clone(), false),
+            (
+                Object::Class(Rc::clone(&point)),
+                Object::Class(Rc::clone(&point)),
+                true,
+            ),
+            (Object::Class(point), Object::Class(class("Point")), false),
+            (list.clone(), list.clone(), true),
+            (list, same_list, false),
+        ];
+
+        for (left, right, expected) in table {
+            assert_eq!(left.is_equal(&right), expected, "{left:?} == {right:?}");
+            assert_eq!(right.is_equal(&left), expected, "{right:?} == {left:?}");
+        }
+    }
+}
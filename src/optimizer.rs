@@ -0,0 +1,472 @@
+use std::cell::Cell;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::error::*;
+use crate::expr::*;
+use crate::inline_cache::*;
+use crate::interpreter::*;
+use crate::object::*;
+use crate::pattern::*;
+use crate::stmt::*;
+use crate::token_type::*;
+
+// Rewrites the resolved tree before it runs: operators whose operands are
+// literals are folded, branches on a literal condition are replaced by the
+// branch that would run, and statements that can never run are removed.
+// Anything that would fail at runtime, like "a" - 1, is left for the backend
+// so the error is still reported when the code runs.
+pub struct Optimizer;
+
+impl StmtVisitor<Option<Rc<Stmt>>> for Optimizer {
+    fn visit_block_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &BlockStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        Ok(Some(Rc::new(Stmt::Block(Rc::new(BlockStmt {
+            statements: self.block(&stmt.statements)?,
+        })))))
+    }
+
+    fn visit_class_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &ClassStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        Ok(Some(Rc::new(Stmt::Class(Rc::new(ClassStmt {
+            name: stmt.name.dup(),
+            superclass: stmt.superclass.clone(),
+            traits: Rc::clone(&stmt.traits),
+            methods: self.block(&stmt.methods)?,
+            class_methods: self.block(&stmt.class_methods)?,
+            setters: self.block(&stmt.setters)?,
+        })))))
+    }
+
+    fn visit_break_stmt(
+        &self,
+        wrapper: &Rc<Stmt>,
+        _: &BreakStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        Ok(Some(Rc::clone(wrapper)))
+    }
+
+    fn visit_do_stmt(&self, _: &Rc<Stmt>, stmt: &DoStmt) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        Ok(Some(Rc::new(Stmt::Do(Rc::new(DoStmt {
+            body: self.branch(&stmt.body)?,
+            condition: self.expr(&stmt.condition)?,
+        })))))
+    }
+
+    fn visit_error_stmt(
+        &self,
+        wrapper: &Rc<Stmt>,
+        _: &ErrorStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        Ok(Some(Rc::clone(wrapper)))
+    }
+
+    fn visit_expression_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &ExpressionStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        let expression = self.expr(&stmt.expression)?;
+        if literal(&expression).is_some() {
+            return Ok(None);
+        }
+        Ok(Some(Rc::new(Stmt::Expression(Rc::new(ExpressionStmt {
+            expression,
+        })))))
+    }
+
+    fn visit_forin_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &ForInStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        Ok(Some(Rc::new(Stmt::ForIn(Rc::new(ForInStmt {
+            name: stmt.name.dup(),
+            iterable: self.expr(&stmt.iterable)?,
+            body: self.branch(&stmt.body)?,
+        })))))
+    }
+
+    fn visit_function_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &FunctionStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        let mut defaults = Vec::new();
+        for default in stmt.defaults.iter() {
+            defaults.push(match default {
+                Some(default) => Some(self.expr(default)?),
+                None => None,
+            });
+        }
+        Ok(Some(Rc::new(Stmt::Function(Rc::new(FunctionStmt {
+            name: stmt.name.dup(),
+            params: Rc::clone(&stmt.params),
+            defaults: Rc::new(defaults),
+            rest: stmt.rest.as_ref().map(|rest| rest.dup()),
+            body: self.block(&stmt.body)?,
+            is_getter: stmt.is_getter,
+        })))))
+    }
+
+    fn visit_if_stmt(&self, _: &Rc<Stmt>, stmt: &IfStmt) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        let condition = self.expr(&stmt.condition)?;
+        if let Some(value) = literal(&condition) {
+            return if value.is_truthy() {
+                self.stmt(&stmt.then_branch)
+            } else if let Some(else_branch) = &stmt.else_branch {
+                self.stmt(else_branch)
+            } else {
+                Ok(None)
+            };
+        }
+
+        let else_branch = match &stmt.else_branch {
+            Some(else_branch) => self.stmt(else_branch)?,
+            None => None,
+        };
+        Ok(Some(Rc::new(Stmt::If(Rc::new(IfStmt {
+            condition,
+            then_branch: self.branch(&stmt.then_branch)?,
+            else_branch,
+        })))))
+    }
+
+    fn visit_match_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &MatchStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        let mut arms = Vec::new();
+        for arm in stmt.arms.iter() {
+            arms.push(MatchArm {
+                token: arm.token.dup(),
+                patterns: arm.patterns.clone(),
+                body: self.branch(&arm.body)?,
+            });
+        }
+        Ok(Some(Rc::new(Stmt::Match(Rc::new(MatchStmt {
+            subject: self.expr(&stmt.subject)?,
+            arms: Rc::new(arms),
+        })))))
+    }
+
+    fn visit_print_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &PrintStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        Ok(Some(Rc::new(Stmt::Print(Rc::new(PrintStmt {
+            keyword: stmt.keyword.dup(),
+            expression: self.expr(&stmt.expression)?,
+        })))))
+    }
+
+    fn visit_return_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &ReturnStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        let value = match &stmt.value {
+            Some(value) => Some(self.expr(value)?),
+            None => None,
+        };
+        Ok(Some(Rc::new(Stmt::Return(Rc::new(ReturnStmt {
+            keyword: stmt.keyword.dup(),
+            value,
+        })))))
+    }
+
+    fn visit_trait_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &TraitStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        Ok(Some(Rc::new(Stmt::Trait(Rc::new(TraitStmt {
+            name: stmt.name.dup(),
+            methods: self.block(&stmt.methods)?,
+        })))))
+    }
+
+    fn visit_var_stmt(&self, _: &Rc<Stmt>, stmt: &VarStmt) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        let initializer = match &stmt.initializer {
+            Some(initializer) => Some(self.expr(initializer)?),
+            None => None,
+        };
+        Ok(Some(Rc::new(Stmt::Var(Rc::new(VarStmt {
+            name: stmt.name.dup(),
+            initializer,
+            is_const: stmt.is_const,
+        })))))
+    }
+
+    fn visit_while_stmt(
+        &self,
+        _: &Rc<Stmt>,
+        stmt: &WhileStmt,
+    ) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        let condition = self.expr(&stmt.condition)?;
+        if literal(&condition).is_some_and(|value| !value.is_truthy()) {
+            return Ok(None);
+        }
+        Ok(Some(Rc::new(Stmt::While(Rc::new(WhileStmt {
+            condition,
+            body: self.branch(&stmt.body)?,
+        })))))
+    }
+}
+
+impl ExprVisitor<Rc<Expr>> for Optimizer {
+    fn visit_assign_expr(&self, _: &Rc<Expr>, expr: &AssignExpr) -> Result<Rc<Expr>, LoxResult> {
+        Ok(Rc::new(Expr::Assign(Rc::new(AssignExpr {
+            name: expr.name.dup(),
+            value: self.expr(&expr.value)?,
+            slot: Cell::new(expr.slot.get()),
+        }))))
+    }
+
+    fn visit_binary_expr(&self, _: &Rc<Expr>, expr: &BinaryExpr) -> Result<Rc<Expr>, LoxResult> {
+        let left = self.expr(&expr.left)?;
+        let right = self.expr(&expr.right)?;
+
+        let op = expr.operator.token_type();
+        if let (Some(l), Some(r)) = (literal(&left), literal(&right)) {
+            if op != TokenType::Is {
+                match primitive_binary(l.clone(), op, r.clone()) {
+                    Object::ArithmeticError | Object::NumsOrStringsError => {}
+                    value => return Ok(constant(value)),
+                }
+            }
+        }
+
+        Ok(Rc::new(Expr::Binary(Rc::new(BinaryExpr {
+            left,
+            operator: expr.operator.dup(),
+            right,
+        }))))
+    }
+
+    fn visit_call_expr(&self, _: &Rc<Expr>, expr: &CallExpr) -> Result<Rc<Expr>, LoxResult> {
+        let mut arguments = Vec::new();
+        for argument in &expr.arguments {
+            arguments.push(self.expr(argument)?);
+        }
+        Ok(Rc::new(Expr::Call(Rc::new(CallExpr {
+            callee: self.expr(&expr.callee)?,
+            paren: expr.paren.dup(),
+            arguments,
+            names: expr
+                .names
+                .iter()
+                .map(|name| name.as_ref().map(|name| name.dup()))
+                .collect(),
+            tail: Cell::new(expr.tail.get()),
+        }))))
+    }
+
+    fn visit_error_expr(&self, wrapper: &Rc<Expr>, _: &ErrorExpr) -> Result<Rc<Expr>, LoxResult> {
+        Ok(Rc::clone(wrapper))
+    }
+
+    fn visit_get_expr(&self, _: &Rc<Expr>, expr: &GetExpr) -> Result<Rc<Expr>, LoxResult> {
+        Ok(Rc::new(Expr::Get(Rc::new(GetExpr {
+            object: self.expr(&expr.object)?,
+            name: expr.name.dup(),
+            cache: InlineCache::new(),
+        }))))
+    }
+
+    fn visit_grouping_expr(
+        &self,
+        _: &Rc<Expr>,
+        expr: &GroupingExpr,
+    ) -> Result<Rc<Expr>, LoxResult> {
+        let expression = self.expr(&expr.expression)?;
+        if literal(&expression).is_some() {
+            return Ok(expression);
+        }
+        Ok(Rc::new(Expr::Grouping(Rc::new(GroupingExpr {
+            expression,
+        }))))
+    }
+
+    fn visit_index_expr(&self, _: &Rc<Expr>, expr: &IndexExpr) -> Result<Rc<Expr>, LoxResult> {
+        Ok(Rc::new(Expr::Index(Rc::new(IndexExpr {
+            object: self.expr(&expr.object)?,
+            bracket: expr.bracket.dup(),
+            index: self.expr(&expr.index)?,
+        }))))
+    }
+
+    fn visit_literal_expr(
+        &self,
+        wrapper: &Rc<Expr>,
+        _: &LiteralExpr,
+    ) -> Result<Rc<Expr>, LoxResult> {
+        Ok(Rc::clone(wrapper))
+    }
+
+    fn visit_logical_expr(&self, _: &Rc<Expr>, expr: &LogicalExpr) -> Result<Rc<Expr>, LoxResult> {
+        let left = self.expr(&expr.left)?;
+        let right = self.expr(&expr.right)?;
+
+        // The result is the left operand when it decides the outcome, and
+        // the right one otherwise
+        if let Some(value) = literal(&left) {
+            let decides = if expr.operator.is(TokenType::Or) {
+                value.is_truthy()
+            } else {
+                !value.is_truthy()
+            };
+            return Ok(if decides { left } else { right });
+        }
+
+        Ok(Rc::new(Expr::Logical(Rc::new(LogicalExpr {
+            left,
+            operator: expr.operator.dup(),
+            right,
+        }))))
+    }
+
+    fn visit_set_expr(&self, _: &Rc<Expr>, expr: &SetExpr) -> Result<Rc<Expr>, LoxResult> {
+        Ok(Rc::new(Expr::Set(Rc::new(SetExpr {
+            object: self.expr(&expr.object)?,
+            name: expr.name.dup(),
+            value: self.expr(&expr.value)?,
+            cache: InlineCache::new(),
+        }))))
+    }
+
+    fn visit_super_expr(&self, wrapper: &Rc<Expr>, _: &SuperExpr) -> Result<Rc<Expr>, LoxResult> {
+        Ok(Rc::clone(wrapper))
+    }
+
+    fn visit_this_expr(&self, wrapper: &Rc<Expr>, _: &ThisExpr) -> Result<Rc<Expr>, LoxResult> {
+        Ok(Rc::clone(wrapper))
+    }
+
+    fn visit_unary_expr(&self, _: &Rc<Expr>, expr: &UnaryExpr) -> Result<Rc<Expr>, LoxResult> {
+        let right = self.expr(&expr.right)?;
+
+        match (expr.operator.token_type(), literal(&right)) {
+            (TokenType::Minus, Some(Object::Num(n))) => return Ok(constant(Object::Num(-n))),
+            (TokenType::Bang, Some(value)) => {
+                return Ok(constant(Object::Bool(!value.is_truthy())))
+            }
+            _ => {}
+        }
+
+        Ok(Rc::new(Expr::Unary(Rc::new(UnaryExpr {
+            operator: expr.operator.dup(),
+            right,
+        }))))
+    }
+
+    fn visit_variable_expr(
+        &self,
+        wrapper: &Rc<Expr>,
+        _: &VariableExpr,
+    ) -> Result<Rc<Expr>, LoxResult> {
+        Ok(Rc::clone(wrapper))
+    }
+}
+
+impl Optimizer {
+    pub fn new() -> Optimizer {
+        Optimizer
+    }
+
+    pub fn optimize(&self, statements: &[Rc<Stmt>]) -> Result<Vec<Rc<Stmt>>, LoxResult> {
+        Ok(self.block(statements)?.deref().clone())
+    }
+
+    fn expr(&self, expr: &Rc<Expr>) -> Result<Rc<Expr>, LoxResult> {
+        expr.accept(expr, self)
+    }
+
+    fn stmt(&self, stmt: &Rc<Stmt>) -> Result<Option<Rc<Stmt>>, LoxResult> {
+        stmt.accept(stmt, self)
+    }
+
+    // A statement that has to stay, as the body of a loop or branch
+    fn branch(&self, stmt: &Rc<Stmt>) -> Result<Rc<Stmt>, LoxResult> {
+        Ok(self.stmt(stmt)?.unwrap_or_else(|| {
+            Rc::new(Stmt::Block(Rc::new(BlockStmt {
+                statements: Rc::new(Vec::new()),
+            })))
+        }))
+    }
+
+    // Nothing after a return or break in the same block can run
+    fn block(&self, statements: &[Rc<Stmt>]) -> Result<Rc<Vec<Rc<Stmt>>>, LoxResult> {
+        let mut optimized = Vec::new();
+        for statement in statements {
+            if let Some(statement) = self.stmt(statement)? {
+                let jumps = matches!(statement.deref(), Stmt::Return(_) | Stmt::Break(_));
+                optimized.push(statement);
+                if jumps {
+                    break;
+                }
+            }
+        }
+        Ok(Rc::new(optimized))
+    }
+}
+
+fn literal(expr: &Expr) -> Option<&Object> {
+    match expr {
+        Expr::Literal(literal) => literal.value.as_ref(),
+        _ => None,
+    }
+}
+
+fn constant(value: Object) -> Rc<Expr> {
+    Rc::new(Expr::Literal(Rc::new(LiteralExpr { value: Some(value) })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_printer::*;
+    use crate::parser::*;
+    use crate::scanner::*;
+
+    fn optimize(source: &str) -> String {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().ok().unwrap();
+        let statements = Parser::new(tokens).parse().statements;
+        let optimized = Optimizer::new().optimize(&statements).ok().unwrap();
+        AstPrinter.print(&optimized).ok().unwrap()
+    }
+
+    #[test]
+    fn folds_literal_operators() {
+        assert_eq!(optimize("print (1 + 2) * -3;"), "(print -9)");
+        assert_eq!(optimize("print \"a\" + 1 + 2;"), "(print \"a12\")");
+        assert_eq!(optimize("print !nil or x;"), "(print true)");
+        assert_eq!(optimize("print 1 < 2 and x;"), "(print x)");
+    }
+
+    #[test]
+    fn leaves_operations_that_fail_at_runtime() {
+        assert_eq!(optimize("print \"a\" - 1;"), "(print (- \"a\" 1))");
+        assert_eq!(optimize("print -\"a\";"), "(print (- \"a\"))");
+        assert_eq!(optimize("print nil + 1;"), "(print (+ nil 1))");
+    }
+
+    #[test]
+    fn removes_code_that_never_runs() {
+        assert_eq!(optimize("if (false) print 1; else print 2;"), "(print 2)");
+        assert_eq!(optimize("while (1 > 2) print 1;"), "");
+        assert_eq!(
+            optimize("fun f() { return 1; print 2; }"),
+            "(fun f ()\n  (return 1))"
+        );
+    }
+}
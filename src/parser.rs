@@ -1,8 +1,12 @@
+use std::cell::Cell;
+use std::ops::Deref;
 use std::rc::Rc;
 
 use crate::error::*;
 use crate::expr::*;
+use crate::inline_cache::*;
 use crate::object::*;
+use crate::pattern::*;
 use crate::stmt::*;
 use crate::token::*;
 use crate::token_type::*;
@@ -10,29 +14,43 @@ use crate::token_type::*;
 pub struct Parser<'a> {
     tokens: &'a [Token],
     current: usize,
-    had_error: bool,
+    errors: Vec<LoxResult>,
+    last_error: Option<usize>,
+    blocks: usize,
+}
+
+// Everything that could be parsed, with failed regions kept as `Error` nodes,
+// along with every syntax error found on the way.
+pub struct Parsed {
+    pub statements: Vec<Rc<Stmt>>,
+    pub errors: Vec<LoxResult>,
+}
+
+impl Parsed {
+    pub fn success(&self) -> bool {
+        self.errors.is_empty()
+    }
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(tokens: &[Token]) -> Parser {
+    pub fn new(tokens: &[Token]) -> Parser<'_> {
         Parser {
             tokens,
             current: 0,
-            had_error: false,
+            errors: Vec::new(),
+            last_error: None,
+            blocks: 0,
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Rc<Stmt>>, LoxResult> {
+    pub fn parse(&mut self) -> Parsed {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            if let Ok(d) = self.declaration() {
-                statements.push(d);
-            }
+            statements.push(self.declaration());
         }
-        if self.had_error {
-            Err(LoxResult::fail())
-        } else {
-            Ok(statements)
+        Parsed {
+            statements,
+            errors: std::mem::take(&mut self.errors),
         }
     }
 
@@ -40,22 +58,32 @@ impl<'a> Parser<'a> {
         self.assignment()
     }
 
-    fn declaration(&mut self) -> Result<Rc<Stmt>, LoxResult> {
+    fn declaration(&mut self) -> Rc<Stmt> {
+        let start = self.current;
         let result = if self.is_match(&[TokenType::Class]) {
             self.class_declaration()
+        } else if self.is_match(&[TokenType::Trait]) {
+            self.trait_declaration()
         } else if self.is_match(&[TokenType::Fun]) {
             self.function("function")
         } else if self.is_match(&[TokenType::Var]) {
-            self.var_declaration()
+            self.var_declaration(false)
+        } else if self.is_match(&[TokenType::Const]) {
+            self.var_declaration(true)
         } else {
             self.statement()
         };
 
-        if result.is_err() {
-            self.synchronize();
-        }
-
-        result
+        result.unwrap_or_else(|_| {
+            self.synchronize(start);
+            let tokens = self.tokens[start..self.current]
+                .iter()
+                .map(Token::dup)
+                .collect();
+            Rc::new(Stmt::Error(Rc::new(ErrorStmt {
+                tokens: Rc::new(tokens),
+            })))
+        })
     }
 
     fn class_declaration(&mut self) -> Result<Rc<Stmt>, LoxResult> {
@@ -65,16 +93,49 @@ impl<'a> Parser<'a> {
             self.consume(TokenType::Identifier, "Expect superclass name.")?;
             Some(Rc::new(Expr::Variable(Rc::new(VariableExpr {
                 name: self.previous().dup(),
+                slot: Cell::new(None),
             }))))
         } else {
             None
         };
 
+        let mut traits = Vec::new();
+        if self.is_match(&[TokenType::With]) {
+            loop {
+                self.consume(TokenType::Identifier, "Expect trait name.")?;
+                traits.push(Rc::new(Expr::Variable(Rc::new(VariableExpr {
+                    name: self.previous().dup(),
+                    slot: Cell::new(None),
+                }))));
+                if !self.is_match(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
         self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
 
         let mut methods = Vec::new();
+        let mut class_methods = Vec::new();
+        let mut setters = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.function("method")?);
+            if self.is_match(&[TokenType::Class]) {
+                class_methods.push(self.function("method")?);
+            } else if self.check(TokenType::Identifier)
+                && self.peek().as_string() == "set"
+                && self.check_next(TokenType::Identifier)
+            {
+                self.advance();
+                let setter = self.function("setter")?;
+                if let Stmt::Function(f) = setter.deref() {
+                    if f.params.len() != 1 || f.rest.is_some() {
+                        self.error(&f.name, "Setter must have exactly one parameter.");
+                    }
+                }
+                setters.push(setter);
+            } else {
+                methods.push(self.function("method")?);
+            }
         }
 
         self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
@@ -82,6 +143,26 @@ impl<'a> Parser<'a> {
         Ok(Rc::new(Stmt::Class(Rc::new(ClassStmt {
             name,
             superclass,
+            traits: Rc::new(traits),
+            methods: Rc::new(methods),
+            class_methods: Rc::new(class_methods),
+            setters: Rc::new(setters),
+        }))))
+    }
+
+    fn trait_declaration(&mut self) -> Result<Rc<Stmt>, LoxResult> {
+        let name = self.consume(TokenType::Identifier, "Expect trait name.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before trait body.")?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after trait body.")?;
+
+        Ok(Rc::new(Stmt::Trait(Rc::new(TraitStmt {
+            name,
             methods: Rc::new(methods),
         }))))
     }
@@ -93,6 +174,10 @@ impl<'a> Parser<'a> {
             return Ok(Rc::new(Stmt::Break(Rc::new(BreakStmt { token }))));
         }
 
+        if self.is_match(&[TokenType::Do]) {
+            return Ok(Rc::new(self.do_statement()?));
+        }
+
         if self.is_match(&[TokenType::For]) {
             return self.for_statement();
         }
@@ -101,6 +186,10 @@ impl<'a> Parser<'a> {
             return Ok(Rc::new(self.if_statement()?));
         }
 
+        if self.is_match(&[TokenType::Match]) {
+            return Ok(Rc::new(self.match_statement()?));
+        }
+
         if self.is_match(&[TokenType::Print]) {
             return Ok(Rc::new(self.print_statement()?));
         }
@@ -122,13 +211,32 @@ impl<'a> Parser<'a> {
         self.expression_statement()
     }
 
+    fn do_statement(&mut self) -> Result<Stmt, LoxResult> {
+        let body = self.statement()?;
+        self.consume(TokenType::While, "Expect 'while' after do body.")?;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = Rc::new(self.expression()?);
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        self.consume(TokenType::SemiColon, "Expect ';' after do-while loop.")?;
+
+        Ok(Stmt::Do(Rc::new(DoStmt { body, condition })))
+    }
+
     fn for_statement(&mut self) -> Result<Rc<Stmt>, LoxResult> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
 
+        if self.check(TokenType::Var) && self.check_next(TokenType::Identifier) {
+            if let Some(token) = self.tokens.get(self.current + 2) {
+                if token.is(TokenType::In) {
+                    return self.for_in_statement();
+                }
+            }
+        }
+
         let initializer = if self.is_match(&[TokenType::SemiColon]) {
             None
         } else if self.is_match(&[TokenType::Var]) {
-            Some(self.var_declaration()?)
+            Some(self.var_declaration(false)?)
         } else {
             Some(self.expression_statement()?)
         };
@@ -182,6 +290,21 @@ impl<'a> Parser<'a> {
         Ok(body)
     }
 
+    fn for_in_statement(&mut self) -> Result<Rc<Stmt>, LoxResult> {
+        self.consume(TokenType::Var, "Expect 'var' in for-in loop.")?;
+        let name = self.consume(TokenType::Identifier, "Expect loop variable name.")?;
+        self.consume(TokenType::In, "Expect 'in' after loop variable.")?;
+        let iterable = Rc::new(self.expression()?);
+        self.consume(TokenType::RightParen, "Expect ')' after for-in clause.")?;
+        let body = self.statement()?;
+
+        Ok(Rc::new(Stmt::ForIn(Rc::new(ForInStmt {
+            name,
+            iterable,
+            body,
+        }))))
+    }
+
     fn if_statement(&mut self) -> Result<Stmt, LoxResult> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = Rc::new(self.expression()?);
@@ -201,10 +324,75 @@ impl<'a> Parser<'a> {
         })))
     }
 
+    fn match_statement(&mut self) -> Result<Stmt, LoxResult> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'match'.")?;
+        let subject = Rc::new(self.expression()?);
+        self.consume(TokenType::RightParen, "Expect ')' after match value.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before match arms.")?;
+
+        let mut arms = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            let token = self.peek().dup();
+            let mut patterns = vec![self.pattern()?];
+            while self.is_match(&[TokenType::Comma]) {
+                patterns.push(self.pattern()?);
+            }
+            self.consume(TokenType::Arrow, "Expect '=>' after match pattern.")?;
+            let body = self.statement()?;
+            arms.push(MatchArm {
+                token,
+                patterns,
+                body,
+            });
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after match arms.")?;
+
+        Ok(Stmt::Match(Rc::new(MatchStmt {
+            subject,
+            arms: Rc::new(arms),
+        })))
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, LoxResult> {
+        if self.is_match(&[TokenType::False]) {
+            return Ok(Pattern::Literal(Object::Bool(false)));
+        }
+        if self.is_match(&[TokenType::True]) {
+            return Ok(Pattern::Literal(Object::Bool(true)));
+        }
+        if self.is_match(&[TokenType::Nil]) {
+            return Ok(Pattern::Literal(Object::Nil));
+        }
+
+        if self.is_match(&[TokenType::Number, TokenType::String]) {
+            return Ok(Pattern::Literal(self.previous().literal.clone().unwrap()));
+        }
+
+        if self.is_match(&[TokenType::Minus]) {
+            let number = self.consume(TokenType::Number, "Expect number after '-' in pattern.")?;
+            if let Some(Object::Num(n)) = number.literal {
+                return Ok(Pattern::Literal(Object::Num(-n)));
+            }
+        }
+
+        if self.check(TokenType::Identifier) && self.peek().as_string() == "_" {
+            self.advance();
+            return Ok(Pattern::Wildcard);
+        }
+
+        let peek = self.peek().dup();
+        Err(self.error(&peek, "Expect literal or '_' in match pattern."))
+    }
+
     fn print_statement(&mut self) -> Result<Stmt, LoxResult> {
+        let keyword = self.previous().dup();
         let value = Rc::new(self.expression()?);
         self.consume(TokenType::SemiColon, "Expect ';' after expression.")?;
-        Ok(Stmt::Print(Rc::new(PrintStmt { expression: value })))
+        Ok(Stmt::Print(Rc::new(PrintStmt {
+            keyword,
+            expression: value,
+        })))
     }
 
     fn return_statement(&mut self) -> Result<Stmt, LoxResult> {
@@ -220,12 +408,15 @@ impl<'a> Parser<'a> {
         Ok(Stmt::Return(Rc::new(ReturnStmt { keyword, value })))
     }
 
-    fn var_declaration(&mut self) -> Result<Rc<Stmt>, LoxResult> {
+    fn var_declaration(&mut self, is_const: bool) -> Result<Rc<Stmt>, LoxResult> {
         let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
 
         let initializer = if self.is_match(&[TokenType::Assign]) {
             Some(Rc::new(self.expression()?))
         } else {
+            if is_const {
+                self.error(&name, "Constant must be initialized.");
+            }
             None
         };
 
@@ -234,7 +425,11 @@ impl<'a> Parser<'a> {
             "Expect ';' after variable declaration.",
         )?;
 
-        Ok(Rc::new(Stmt::Var(Rc::new(VarStmt { name, initializer }))))
+        Ok(Rc::new(Stmt::Var(Rc::new(VarStmt {
+            name,
+            initializer,
+            is_const,
+        }))))
     }
 
     fn while_statement(&mut self) -> Result<Stmt, LoxResult> {
@@ -257,20 +452,60 @@ impl<'a> Parser<'a> {
     fn function(&mut self, kind: &str) -> Result<Rc<Stmt>, LoxResult> {
         let name = self.consume(TokenType::Identifier, &format!("Expect {kind} name"))?;
 
+        if kind == "method" && self.is_match(&[TokenType::LeftBrace]) {
+            let body = Rc::new(self.block()?);
+            return Ok(Rc::new(Stmt::Function(Rc::new(FunctionStmt {
+                name,
+                params: Rc::new(Vec::new()),
+                defaults: Rc::new(Vec::new()),
+                rest: None,
+                body,
+                is_getter: true,
+            }))));
+        }
+
         self.consume(
             TokenType::LeftParen,
             &format!("Expect '(' after {kind} name."),
         )?;
 
         let mut params = Vec::new();
+        let mut defaults = Vec::new();
+        let mut rest = None;
         if !self.check(TokenType::RightParen) {
-            params.push(self.consume(TokenType::Identifier, "Expect paramter name")?);
-            while self.is_match(&[TokenType::Comma]) {
-                if params.len() >= 255 && !self.had_error {
+            loop {
+                if params.len() == 255 {
                     let peek = self.peek().dup();
                     self.error(&peek, "Can't have more than 255 parameters.");
                 }
-                params.push(self.consume(TokenType::Identifier, "Expect paramter name")?);
+
+                if self.is_match(&[TokenType::Ellipsis]) {
+                    rest =
+                        Some(self.consume(TokenType::Identifier, "Expect rest parameter name.")?);
+                    if !self.check(TokenType::RightParen) {
+                        let peek = self.peek().dup();
+                        return Err(self.error(&peek, "Rest parameter must be last."));
+                    }
+                    break;
+                }
+
+                let param = self.consume(TokenType::Identifier, "Expect paramter name")?;
+                if self.is_match(&[TokenType::Assign]) {
+                    defaults.push(Some(Rc::new(self.expression()?)));
+                } else {
+                    if defaults.iter().any(Option::is_some) {
+                        self.error(
+                            &param,
+                            "Parameter without a default can't follow one with a default.",
+                        );
+                    }
+                    defaults.push(None);
+                }
+                params.push(param);
+
+                if !self.is_match(&[TokenType::Comma]) {
+                    break;
+                }
             }
         }
 
@@ -285,16 +520,21 @@ impl<'a> Parser<'a> {
         Ok(Rc::new(Stmt::Function(Rc::new(FunctionStmt {
             name,
             params: Rc::new(params),
+            defaults: Rc::new(defaults),
+            rest,
             body,
+            is_getter: false,
         }))))
     }
 
     fn block(&mut self) -> Result<Vec<Rc<Stmt>>, LoxResult> {
         let mut statements = Vec::new();
 
+        self.blocks += 1;
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            statements.push(self.declaration());
         }
+        self.blocks -= 1;
 
         self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
 
@@ -312,12 +552,14 @@ impl<'a> Parser<'a> {
                 return Ok(Expr::Assign(Rc::new(AssignExpr {
                     name: expr.name.dup(),
                     value: Rc::new(value),
+                    slot: Cell::new(None),
                 })));
             } else if let Expr::Get(get) = expr {
                 return Ok(Expr::Set(Rc::new(SetExpr {
                     object: Rc::clone(&get.object),
                     name: get.name.dup(),
                     value: Rc::new(value),
+                    cache: InlineCache::new(),
                 })));
             }
 
@@ -383,6 +625,7 @@ impl<'a> Parser<'a> {
             TokenType::GreaterEqual,
             TokenType::Less,
             TokenType::LessEqual,
+            TokenType::Is,
         ]) {
             let operator = self.previous().dup();
             let right = self.term()?;
@@ -443,18 +686,16 @@ impl<'a> Parser<'a> {
 
     fn finish_call(&mut self, callee: &Rc<Expr>) -> Result<Expr, LoxResult> {
         let mut arguments = Vec::new();
+        let mut names = Vec::new();
 
         if !self.check(TokenType::RightParen) {
-            arguments.push(Rc::new(self.expression()?));
+            self.argument(&mut arguments, &mut names)?;
             while self.is_match(&[TokenType::Comma]) {
-                if arguments.len() >= 255 {
-                    if !self.had_error {
-                        let peek = self.peek().dup();
-                        return Err(self.error(&peek, "Can't have more than 255 arguments."));
-                    }
-                } else {
-                    arguments.push(Rc::new(self.expression()?));
+                if arguments.len() == 255 {
+                    let peek = self.peek().dup();
+                    self.error(&peek, "Can't have more than 255 arguments.");
                 }
+                self.argument(&mut arguments, &mut names)?;
             }
         }
 
@@ -464,9 +705,43 @@ impl<'a> Parser<'a> {
             callee: Rc::clone(callee),
             paren,
             arguments,
+            names,
+            tail: Cell::new(false),
         })))
     }
 
+    // A call argument, either positional or `name: value`
+    fn argument(
+        &mut self,
+        arguments: &mut Vec<Rc<Expr>>,
+        names: &mut Vec<Option<Token>>,
+    ) -> Result<(), LoxResult> {
+        if self.check(TokenType::Identifier) && self.check_next(TokenType::Colon) {
+            let name = self.advance().dup();
+            self.advance();
+            let duplicate = names
+                .iter()
+                .flatten()
+                .any(|n| n.as_string() == name.as_string());
+            if duplicate {
+                self.error(
+                    &name,
+                    &format!("Argument '{}' given more than once.", name.as_string()),
+                );
+            }
+            names.push(Some(name));
+        } else {
+            if names.iter().any(Option::is_some) {
+                let peek = self.peek().dup();
+                self.error(&peek, "Positional argument can't follow a named argument.");
+            }
+            names.push(None);
+        }
+
+        arguments.push(Rc::new(self.expression()?));
+        Ok(())
+    }
+
     fn call(&mut self) -> Result<Expr, LoxResult> {
         let mut expr = self.primary()?;
 
@@ -479,6 +754,16 @@ impl<'a> Parser<'a> {
                 expr = Expr::Get(Rc::new(GetExpr {
                     object: Rc::new(expr),
                     name,
+                    cache: InlineCache::new(),
+                }));
+            } else if self.is_match(&[TokenType::LeftBracket]) {
+                let bracket = self.previous().dup();
+                let index = self.expression()?;
+                self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+                expr = Expr::Index(Rc::new(IndexExpr {
+                    object: Rc::new(expr),
+                    bracket,
+                    index: Rc::new(index),
                 }));
             } else {
                 break;
@@ -515,18 +800,24 @@ impl<'a> Parser<'a> {
             let keyword = self.previous().dup();
             self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
             let method = self.consume(TokenType::Identifier, "Expect superclass method name.")?;
-            return Ok(Expr::Super(Rc::new(SuperExpr { keyword, method })));
+            return Ok(Expr::Super(Rc::new(SuperExpr {
+                keyword,
+                method,
+                slot: Cell::new(None),
+            })));
         }
 
         if self.is_match(&[TokenType::This]) {
             return Ok(Expr::This(Rc::new(ThisExpr {
                 keyword: self.previous().dup(),
+                slot: Cell::new(None),
             })));
         }
 
         if self.is_match(&[TokenType::Identifier]) {
             return Ok(Expr::Variable(Rc::new(VariableExpr {
                 name: self.previous().dup(),
+                slot: Cell::new(None),
             })));
         }
 
@@ -538,8 +829,13 @@ impl<'a> Parser<'a> {
             })));
         }
 
-        let peek = self.peek().dup();
-        Err(self.error(&peek, "Expect expression."))
+        // Leave closing tokens and statement keywords for the caller to recover at
+        let token = self.peek().dup();
+        self.error(&token, "Expect expression.");
+        if !self.at_closing_token() && !self.at_statement_start() {
+            self.advance();
+        }
+        Ok(Expr::Error(Rc::new(ErrorExpr { token })))
     }
 
     fn consume(&mut self, ttype: TokenType, message: &str) -> Result<Token, LoxResult> {
@@ -551,37 +847,85 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // Records the error, but only reports the first one at a given position so
+    // a single mistake doesn't cascade
     fn error(&mut self, token: &Token, message: &str) -> LoxResult {
-        self.had_error = true;
+        let error = LoxResult::ParseError {
+            token: token.dup(),
+            message: message.to_string(),
+        };
+        if self.last_error == Some(self.current) {
+            return error;
+        }
+        self.last_error = Some(self.current);
+        self.errors.push(error);
         LoxResult::parse_error(token, message)
     }
 
-    fn synchronize(&mut self) {
-        self.advance();
-
+    // Skips ahead from a failed declaration to the end of the statement, the
+    // start of another one, or the '}' closing the enclosing block
+    fn synchronize(&mut self, start: usize) {
         while !self.is_at_end() {
-            if self.previous().is(TokenType::SemiColon) {
+            if self.current > start
+                && (self.at_statement_start()
+                    || self.blocks > 0 && self.check(TokenType::RightBrace))
+            {
                 return;
             }
 
-            if matches!(
-                self.peek().token_type(),
-                TokenType::Class
-                    | TokenType::Fun
-                    | TokenType::Var
-                    | TokenType::For
-                    | TokenType::If
-                    | TokenType::While
-                    | TokenType::Print
-                    | TokenType::Return
-            ) {
+            self.advance();
+
+            if self.previous().is(TokenType::SemiColon) {
                 return;
             }
 
-            self.advance();
+            // A brace opened inside the failed region belongs to it, so the
+            // region ends with the matching '}' rather than resuming inside
+            if self.previous().is(TokenType::LeftBrace) {
+                let mut depth = 1;
+                while depth > 0 && !self.is_at_end() {
+                    match self.advance().token_type() {
+                        TokenType::LeftBrace => depth += 1,
+                        TokenType::RightBrace => depth -= 1,
+                        _ => {}
+                    }
+                }
+                return;
+            }
         }
     }
 
+    fn at_statement_start(&self) -> bool {
+        matches!(
+            self.peek().token_type(),
+            TokenType::Class
+                | TokenType::Trait
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::Const
+                | TokenType::Break
+                | TokenType::Do
+                | TokenType::For
+                | TokenType::If
+                | TokenType::Match
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+        )
+    }
+
+    fn at_closing_token(&self) -> bool {
+        matches!(
+            self.peek().token_type(),
+            TokenType::SemiColon
+                | TokenType::Comma
+                | TokenType::RightParen
+                | TokenType::RightBrace
+                | TokenType::RightBracket
+                | TokenType::Eof
+        )
+    }
+
     fn is_match(&mut self, types: &[TokenType]) -> bool {
         for &t in types {
             if self.check(t) {
@@ -601,6 +945,13 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn check_next(&self, ttype: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.is(ttype),
+            None => false,
+        }
+    }
+
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -620,3 +971,45 @@ impl<'a> Parser<'a> {
         self.tokens.get(self.current - 1).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::*;
+
+    fn parse(source: &str) -> Parsed {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().ok().unwrap();
+        Parser::new(tokens).parse()
+    }
+
+    #[test]
+    fn keeps_statements_around_errors() {
+        let parsed = parse("print 1; var = 2; print 3; { print 4 } print;");
+        assert_eq!(parsed.errors.len(), 3);
+
+        let kinds: Vec<&str> = parsed
+            .statements
+            .iter()
+            .map(|s| match s.deref() {
+                Stmt::Print(p) if matches!(*p.expression, Expr::Error(_)) => "print error",
+                Stmt::Print(_) => "print",
+                Stmt::Error(_) => "error",
+                Stmt::Block(b) if matches!(*b.statements[0], Stmt::Error(_)) => "block error",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec!["print", "error", "print", "block error", "print error"]
+        );
+    }
+
+    #[test]
+    fn too_many_arguments_still_parses_the_call() {
+        let arguments = vec!["1"; 300].join(", ");
+        let parsed = parse(&format!("f({arguments}); print 2;"));
+        assert_eq!(parsed.errors.len(), 1);
+        assert_eq!(parsed.statements.len(), 2);
+    }
+}
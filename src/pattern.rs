@@ -0,0 +1,23 @@
+use std::rc::Rc;
+
+use crate::object::*;
+use crate::stmt::*;
+use crate::token::*;
+
+#[derive(Clone)]
+pub enum Pattern {
+    Literal(Object),
+    Wildcard,
+}
+
+pub struct MatchArm {
+    pub token: Token,
+    pub patterns: Vec<Pattern>,
+    pub body: Rc<Stmt>,
+}
+
+impl MatchArm {
+    pub fn is_wildcard(&self) -> bool {
+        self.patterns.iter().any(|p| matches!(p, Pattern::Wildcard))
+    }
+}
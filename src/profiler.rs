@@ -0,0 +1,287 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::expr::*;
+use crate::stmt::*;
+use crate::symbol::*;
+
+// Times calls and statements for --profile. A function's exclusive time is
+// its inclusive time less that of the calls it makes, and a line's time is
+// likewise what its statements took less the statements nested in them.
+// Call stacks are kept as paths in a tree, so entering a call only looks
+// up its parent's path and the function.
+#[derive(Default)]
+pub struct Profiler {
+    functions: Vec<Function>,
+    function_ids: HashMap<(Symbol, usize), usize>,
+    paths: Vec<Path>,
+    path_ids: HashMap<(Option<usize>, usize), usize>,
+    calls: Vec<Frame>,
+    statements: Vec<Frame>,
+    lines: HashMap<usize, Line>,
+}
+
+struct Function {
+    name: Symbol,
+    line: usize,
+    calls: usize,
+    // How many calls of this function are running, so time in recursive
+    // calls only counts once towards its inclusive time
+    active: usize,
+    inclusive: Duration,
+    exclusive: Duration,
+}
+
+struct Path {
+    parent: Option<usize>,
+    function: usize,
+    exclusive: Duration,
+}
+
+struct Frame {
+    id: usize,
+    start: Instant,
+    children: Duration,
+}
+
+#[derive(Default)]
+struct Line {
+    count: usize,
+    time: Duration,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts a call to the function `name` declared on `line`, or a native
+    // when the line is 0
+    pub fn enter(&mut self, name: Symbol, line: usize) {
+        let function = *self.function_ids.entry((name, line)).or_insert_with(|| {
+            self.functions.push(Function {
+                name,
+                line,
+                calls: 0,
+                active: 0,
+                inclusive: Duration::ZERO,
+                exclusive: Duration::ZERO,
+            });
+            self.functions.len() - 1
+        });
+        self.functions[function].calls += 1;
+        self.functions[function].active += 1;
+
+        let parent = self.calls.last().map(|frame| frame.id);
+        let path = *self.path_ids.entry((parent, function)).or_insert_with(|| {
+            self.paths.push(Path {
+                parent,
+                function,
+                exclusive: Duration::ZERO,
+            });
+            self.paths.len() - 1
+        });
+        self.calls.push(Frame {
+            id: path,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    pub fn exit(&mut self) {
+        let frame = self.calls.pop().expect("exit without a call");
+        let inclusive = frame.start.elapsed();
+        let exclusive = inclusive.saturating_sub(frame.children);
+        if let Some(parent) = self.calls.last_mut() {
+            parent.children += inclusive;
+        }
+
+        let path = &mut self.paths[frame.id];
+        path.exclusive += exclusive;
+        let function = &mut self.functions[path.function];
+        function.exclusive += exclusive;
+        function.active -= 1;
+        if function.active == 0 {
+            function.inclusive += inclusive;
+        }
+    }
+
+    pub fn begin_statement(&mut self) {
+        self.statements.push(Frame {
+            id: 0,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    pub fn end_statement(&mut self, line: usize) {
+        let frame = self.statements.pop().expect("end without a statement");
+        let elapsed = frame.start.elapsed();
+        if let Some(parent) = self.statements.last_mut() {
+            parent.children += elapsed;
+        }
+        let stats = self.lines.entry(line).or_default();
+        stats.count += 1;
+        stats.time += elapsed.saturating_sub(frame.children);
+    }
+
+    // Functions by exclusive time, then the hottest lines
+    pub fn report(&self) -> String {
+        let total: Duration = self.functions.iter().map(|f| f.exclusive).sum();
+        let percent = |time: Duration| {
+            if total.is_zero() {
+                0.0
+            } else {
+                100.0 * time.as_secs_f64() / total.as_secs_f64()
+            }
+        };
+
+        let mut report = String::new();
+        let _ = writeln!(report, "Total time: {:.3} ms", millis(total));
+        let _ = writeln!(
+            report,
+            "\n{:<24} {:>10} {:>14} {:>14} {:>7}",
+            "function", "calls", "inclusive ms", "exclusive ms", "self %"
+        );
+        let mut functions: Vec<&Function> = self.functions.iter().collect();
+        functions.sort_by_key(|function| Reverse(function.exclusive));
+        for function in functions {
+            let _ = writeln!(
+                report,
+                "{:<24} {:>10} {:>14.3} {:>14.3} {:>7.1}",
+                self.label(function),
+                function.calls,
+                millis(function.inclusive),
+                millis(function.exclusive),
+                percent(function.exclusive)
+            );
+        }
+
+        let _ = writeln!(
+            report,
+            "\n{:<8} {:>10} {:>14} {:>7}",
+            "line", "runs", "time ms", "time %"
+        );
+        let mut lines: Vec<(&usize, &Line)> = self.lines.iter().collect();
+        lines.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+        for (line, stats) in lines.into_iter().take(HOT_LINES) {
+            let _ = writeln!(
+                report,
+                "{:<8} {:>10} {:>14.3} {:>7.1}",
+                line,
+                stats.count,
+                millis(stats.time),
+                percent(stats.time)
+            );
+        }
+        report
+    }
+
+    // One line per call stack with its exclusive time in microseconds, in
+    // the folded format flamegraph tools read
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for path in self.paths.iter() {
+            let micros = path.exclusive.as_micros();
+            if micros == 0 {
+                continue;
+            }
+            let mut names = vec![self.label(&self.functions[path.function])];
+            let mut parent = path.parent;
+            while let Some(id) = parent {
+                names.push(self.label(&self.functions[self.paths[id].function]));
+                parent = self.paths[id].parent;
+            }
+            names.reverse();
+            let _ = writeln!(folded, "{} {micros}", names.join(";"));
+        }
+        folded
+    }
+
+    fn label(&self, function: &Function) -> String {
+        if function.line == 0 {
+            function.name.to_string()
+        } else {
+            format!("{}:{}", function.name, function.line)
+        }
+    }
+}
+
+const HOT_LINES: usize = 20;
+
+fn millis(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+// The line a statement is reported on. Blocks aren't timed themselves, only
+// the statements in them.
+pub fn statement_line(stmt: &Stmt) -> Option<usize> {
+    match stmt {
+        Stmt::Block(_) => None,
+        Stmt::Class(stmt) => Some(stmt.name.line),
+        Stmt::Break(stmt) => Some(stmt.token.line),
+        Stmt::Do(stmt) => expression_line(&stmt.condition),
+        Stmt::Error(stmt) => stmt.tokens.first().map(|token| token.line),
+        Stmt::Expression(stmt) => expression_line(&stmt.expression),
+        Stmt::ForIn(stmt) => Some(stmt.name.line),
+        Stmt::Function(stmt) => Some(stmt.name.line),
+        Stmt::If(stmt) => expression_line(&stmt.condition),
+        Stmt::Match(stmt) => expression_line(&stmt.subject),
+        Stmt::Print(stmt) => Some(stmt.keyword.line),
+        Stmt::Return(stmt) => Some(stmt.keyword.line),
+        Stmt::Trait(stmt) => Some(stmt.name.line),
+        Stmt::Var(stmt) => Some(stmt.name.line),
+        Stmt::While(stmt) => expression_line(&stmt.condition),
+    }
+}
+
+fn expression_line(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Assign(expr) => Some(expr.name.line),
+        Expr::Binary(expr) => expression_line(&expr.left).or(Some(expr.operator.line)),
+        Expr::Call(expr) => expression_line(&expr.callee).or(Some(expr.paren.line)),
+        Expr::Error(expr) => Some(expr.token.line),
+        Expr::Get(expr) => expression_line(&expr.object).or(Some(expr.name.line)),
+        Expr::Grouping(expr) => expression_line(&expr.expression),
+        Expr::Index(expr) => expression_line(&expr.object).or(Some(expr.bracket.line)),
+        Expr::Literal(_) => None,
+        Expr::Logical(expr) => expression_line(&expr.left).or(Some(expr.operator.line)),
+        Expr::Set(expr) => expression_line(&expr.object).or(Some(expr.name.line)),
+        Expr::Super(expr) => Some(expr.keyword.line),
+        Expr::This(expr) => Some(expr.keyword.line),
+        Expr::Unary(expr) => Some(expr.operator.line),
+        Expr::Variable(expr) => Some(expr.name.line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_calls_and_folds_stacks() {
+        let mut profiler = Profiler::new();
+        let (script, fib) = (Symbol::intern("<script>"), Symbol::intern("fib"));
+        profiler.enter(script, 0);
+        for _ in 0..2 {
+            profiler.enter(fib, 3);
+            profiler.enter(fib, 3);
+            std::thread::sleep(Duration::from_millis(1));
+            profiler.exit();
+            profiler.exit();
+        }
+        profiler.exit();
+
+        let fib = &profiler.functions[1];
+        assert_eq!(fib.calls, 4);
+        assert!(fib.inclusive >= fib.exclusive);
+        assert!(profiler.functions[0].inclusive >= fib.inclusive);
+
+        let folded = profiler.folded();
+        assert!(folded
+            .lines()
+            .any(|l| l.starts_with("<script>;fib:3;fib:3 ")));
+    }
+}
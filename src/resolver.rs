@@ -1,21 +1,40 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::rc::Rc;
 
+use crate::environment::*;
 use crate::error::*;
 use crate::expr::*;
-use crate::interpreter::*;
 use crate::stmt::*;
+use crate::symbol::*;
 use crate::token::*;
 
-pub struct Resolver<'a> {
-    interpreter: &'a Interpreter,
-    scopes: RefCell<Vec<RefCell<HashMap<String, bool>>>>,
+pub struct Resolver {
+    scopes: RefCell<Vec<RefCell<HashMap<Symbol, Local>>>>,
     had_error: RefCell<bool>,
     current_function: RefCell<FunctionType>,
     current_class: RefCell<ClassType>,
     in_while: RefCell<bool>,
+    // Methods of the traits declared at the top level, which have no scope
+    // entry to carry them
+    global_traits: RefCell<HashMap<Symbol, Rc<[Symbol]>>>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Binding {
+    Declared,
+    Variable,
+    Constant,
+}
+
+// A name declared in a scope, along with the slot it will occupy at runtime
+// and, for a trait, the names of its methods
+#[derive(Clone)]
+struct Local {
+    binding: Binding,
+    slot: usize,
+    trait_methods: Option<Rc<[Symbol]>>,
 }
 
 #[derive(PartialEq)]
@@ -31,10 +50,11 @@ enum ClassType {
     None,
     Class,
     Subclass,
+    Trait,
 }
 
-impl<'a> StmtVisitor<()> for Resolver<'a> {
-    fn visit_class_stmt(&self, _: Rc<Stmt>, stmt: &ClassStmt) -> Result<(), LoxResult> {
+impl StmtVisitor<()> for Resolver {
+    fn visit_class_stmt(&self, _: &Rc<Stmt>, stmt: &ClassStmt) -> Result<(), LoxResult> {
         let enclosing_class = self.current_class.replace(ClassType::Class);
 
         self.declare(&stmt.name);
@@ -44,32 +64,32 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
             self.current_class.replace(ClassType::Subclass);
 
             if let Expr::Variable(v) = &superclass.deref() {
-                if v.name.as_string() == stmt.name.as_string() {
+                if v.name.lexeme() == stmt.name.lexeme() {
                     self.error(&v.name, "A class can't inherit from itself.");
                 }
             }
-            self.resolve_expr(superclass.clone())?;
+            self.resolve_expr(superclass)?;
+        }
+
+        for t in stmt.traits.iter() {
+            self.resolve_expr(t)?;
+        }
+        self.check_trait_conflicts(stmt);
 
+        if stmt.superclass.is_some() {
             self.begin_scope();
-            self.scopes
-                .borrow()
-                .last()
-                .unwrap()
-                .borrow_mut()
-                .insert("super".to_string(), true);
+            self.define_keyword(Symbol::SUPER);
         }
 
         self.begin_scope();
-        self.scopes
-            .borrow()
-            .last()
-            .unwrap()
-            .borrow_mut()
-            .insert("this".to_string(), true);
+        self.define_keyword(Symbol::THIS);
 
         for method in stmt.methods.deref() {
             if let Stmt::Function(method) = method.deref() {
-                let declaration = if method.name.as_string() == "init" {
+                let declaration = if method.name.lexeme() == Symbol::INIT {
+                    if method.is_getter {
+                        self.error(&method.name, "Can't declare 'init' as a getter.");
+                    }
                     FunctionType::Initializer
                 } else {
                     FunctionType::Method
@@ -83,6 +103,17 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
             }
         }
 
+        for method in stmt.class_methods.iter().chain(stmt.setters.iter()) {
+            if let Stmt::Function(method) = method.deref() {
+                self.resolve_function(method, FunctionType::Method)?;
+            } else {
+                return Err(LoxResult::runtime_error(
+                    &stmt.name,
+                    "Class method did not resolve into a function statement",
+                ));
+            }
+        }
+
         self.end_scope();
         if stmt.superclass.is_some() {
             self.end_scope();
@@ -92,22 +123,61 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
         Ok(())
     }
 
-    fn visit_return_stmt(&self, _: Rc<Stmt>, stmt: &ReturnStmt) -> Result<(), LoxResult> {
+    fn visit_trait_stmt(&self, _: &Rc<Stmt>, stmt: &TraitStmt) -> Result<(), LoxResult> {
+        let enclosing_class = self.current_class.replace(ClassType::Trait);
+
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+
+        self.begin_scope();
+        self.define_keyword(Symbol::THIS);
+
+        let mut names = Vec::new();
+        for method in stmt.methods.deref() {
+            if let Stmt::Function(method) = method.deref() {
+                let declaration = if method.name.lexeme() == Symbol::INIT {
+                    FunctionType::Initializer
+                } else {
+                    FunctionType::Method
+                };
+                self.resolve_function(method, declaration)?;
+                names.push(method.name.lexeme());
+            } else {
+                return Err(LoxResult::runtime_error(
+                    &stmt.name,
+                    "Trait method did not resolve into a function statement",
+                ));
+            }
+        }
+
+        self.end_scope();
+        self.define_trait(&stmt.name, names.into());
+
+        self.current_class.replace(enclosing_class);
+        Ok(())
+    }
+
+    fn visit_return_stmt(&self, _: &Rc<Stmt>, stmt: &ReturnStmt) -> Result<(), LoxResult> {
         if *self.current_function.borrow() == FunctionType::None {
             self.error(&stmt.keyword, "Can't return from top-level code.");
         }
 
-        if let Some(value) = stmt.value.clone() {
+        if let Some(value) = &stmt.value {
             if *self.current_function.borrow() == FunctionType::Initializer {
                 self.error(&stmt.keyword, "Can't return a value from an initializer.");
             }
 
+            // Nothing is left to do in this function once the call returns,
+            // so the backends can reuse its frame
+            if let Expr::Call(call) = value.deref() {
+                call.tail.set(true);
+            }
             self.resolve_expr(value)?;
         }
         Ok(())
     }
 
-    fn visit_function_stmt(&self, _: Rc<Stmt>, stmt: &FunctionStmt) -> Result<(), LoxResult> {
+    fn visit_function_stmt(&self, _: &Rc<Stmt>, stmt: &FunctionStmt) -> Result<(), LoxResult> {
         self.declare(&stmt.name);
         self.define(&stmt.name);
 
@@ -116,7 +186,7 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
         Ok(())
     }
 
-    fn visit_break_stmt(&self, _: Rc<Stmt>, stmt: &BreakStmt) -> Result<(), LoxResult> {
+    fn visit_break_stmt(&self, _: &Rc<Stmt>, stmt: &BreakStmt) -> Result<(), LoxResult> {
         if !*self.in_while.borrow() {
             self.error(&stmt.token, "Break statement outside of a while/for loop.");
         }
@@ -124,57 +194,110 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
         Ok(())
     }
 
-    fn visit_while_stmt(&self, _: Rc<Stmt>, stmt: &WhileStmt) -> Result<(), LoxResult> {
+    fn visit_error_stmt(&self, _: &Rc<Stmt>, _stmt: &ErrorStmt) -> Result<(), LoxResult> {
+        Ok(())
+    }
+
+    fn visit_do_stmt(&self, _: &Rc<Stmt>, stmt: &DoStmt) -> Result<(), LoxResult> {
         let previous_nesting = self.in_while.replace(true);
-        self.resolve_expr(stmt.condition.clone())?;
-        self.resolve_stmt(stmt.body.clone())?;
+        self.resolve_stmt(&stmt.body)?;
+        self.resolve_expr(&stmt.condition)?;
 
         self.in_while.replace(previous_nesting);
         Ok(())
     }
 
-    fn visit_if_stmt(&self, _: Rc<Stmt>, stmt: &IfStmt) -> Result<(), LoxResult> {
-        self.resolve_expr(stmt.condition.clone())?;
-        self.resolve_stmt(stmt.then_branch.clone())?;
-        if let Some(else_branch) = stmt.else_branch.clone() {
+    fn visit_while_stmt(&self, _: &Rc<Stmt>, stmt: &WhileStmt) -> Result<(), LoxResult> {
+        let previous_nesting = self.in_while.replace(true);
+        self.resolve_expr(&stmt.condition)?;
+        self.resolve_stmt(&stmt.body)?;
+
+        self.in_while.replace(previous_nesting);
+        Ok(())
+    }
+
+    fn visit_match_stmt(&self, _: &Rc<Stmt>, stmt: &MatchStmt) -> Result<(), LoxResult> {
+        self.resolve_expr(&stmt.subject)?;
+
+        let mut seen_wildcard = false;
+        for arm in stmt.arms.iter() {
+            if seen_wildcard {
+                self.warning(&arm.token, "Unreachable match arm after '_'.");
+            }
+            seen_wildcard |= arm.is_wildcard();
+            self.resolve_stmt(&arm.body)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_forin_stmt(&self, _: &Rc<Stmt>, stmt: &ForInStmt) -> Result<(), LoxResult> {
+        self.resolve_expr(&stmt.iterable)?;
+
+        let previous_nesting = self.in_while.replace(true);
+        self.begin_scope();
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+        self.resolve_stmt(&stmt.body)?;
+        self.end_scope();
+        self.in_while.replace(previous_nesting);
+
+        Ok(())
+    }
+
+    fn visit_if_stmt(&self, _: &Rc<Stmt>, stmt: &IfStmt) -> Result<(), LoxResult> {
+        self.resolve_expr(&stmt.condition)?;
+        self.resolve_stmt(&stmt.then_branch)?;
+        if let Some(else_branch) = &stmt.else_branch {
             self.resolve_stmt(else_branch)?;
         }
         Ok(())
     }
 
-    fn visit_block_stmt(&self, _: Rc<Stmt>, stmt: &BlockStmt) -> Result<(), LoxResult> {
+    fn visit_block_stmt(&self, _: &Rc<Stmt>, stmt: &BlockStmt) -> Result<(), LoxResult> {
         self.begin_scope();
         self.resolve(&stmt.statements)?;
         self.end_scope();
         Ok(())
     }
 
-    fn visit_expression_stmt(&self, _: Rc<Stmt>, stmt: &ExpressionStmt) -> Result<(), LoxResult> {
-        self.resolve_expr(stmt.expression.clone())?;
+    fn visit_expression_stmt(&self, _: &Rc<Stmt>, stmt: &ExpressionStmt) -> Result<(), LoxResult> {
+        self.resolve_expr(&stmt.expression)?;
         Ok(())
     }
-    fn visit_print_stmt(&self, _: Rc<Stmt>, stmt: &PrintStmt) -> Result<(), LoxResult> {
-        self.resolve_expr(stmt.expression.clone())?;
+    fn visit_print_stmt(&self, _: &Rc<Stmt>, stmt: &PrintStmt) -> Result<(), LoxResult> {
+        self.resolve_expr(&stmt.expression)?;
         Ok(())
     }
 
-    fn visit_var_stmt(&self, _: Rc<Stmt>, stmt: &VarStmt) -> Result<(), LoxResult> {
+    fn visit_var_stmt(&self, _: &Rc<Stmt>, stmt: &VarStmt) -> Result<(), LoxResult> {
         self.declare(&stmt.name);
-        if let Some(init) = stmt.initializer.clone() {
+        if let Some(init) = &stmt.initializer {
             self.resolve_expr(init)?;
         }
-        self.define(&stmt.name);
+        if stmt.is_const {
+            self.define_as(&stmt.name, Binding::Constant);
+        } else {
+            self.define(&stmt.name);
+        }
         Ok(())
     }
 }
 
-impl<'a> ExprVisitor<()> for Resolver<'a> {
-    fn visit_super_expr(&self, wrapper: Rc<Expr>, expr: &SuperExpr) -> Result<(), LoxResult> {
+impl ExprVisitor<()> for Resolver {
+    fn visit_error_expr(&self, _: &Rc<Expr>, _expr: &ErrorExpr) -> Result<(), LoxResult> {
+        Ok(())
+    }
+
+    fn visit_super_expr(&self, _: &Rc<Expr>, expr: &SuperExpr) -> Result<(), LoxResult> {
         match self.current_class.borrow().deref() {
             ClassType::None => {
                 self.error(&expr.keyword, "Can't use 'super' outside of a class.");
             }
             ClassType::Subclass => {}
+            ClassType::Trait => {
+                self.error(&expr.keyword, "Can't use 'super' in a trait.");
+            }
             _ => {
                 self.error(
                     &expr.keyword,
@@ -183,74 +306,85 @@ impl<'a> ExprVisitor<()> for Resolver<'a> {
             }
         }
 
-        self.resolve_local(wrapper, &expr.keyword);
+        self.resolve_local(&expr.slot, &expr.keyword);
         Ok(())
     }
 
-    fn visit_this_expr(&self, wrapper: Rc<Expr>, expr: &ThisExpr) -> Result<(), LoxResult> {
+    fn visit_this_expr(&self, _: &Rc<Expr>, expr: &ThisExpr) -> Result<(), LoxResult> {
         if *self.current_class.borrow() == ClassType::None {
             self.error(&expr.keyword, "Can't use 'this' outside of a class.");
             return Ok(());
         }
 
-        self.resolve_local(wrapper, &expr.keyword);
+        self.resolve_local(&expr.slot, &expr.keyword);
         Ok(())
     }
 
-    fn visit_set_expr(&self, _: Rc<Expr>, expr: &SetExpr) -> Result<(), LoxResult> {
-        self.resolve_expr(expr.value.clone())?;
-        self.resolve_expr(expr.object.clone())?;
+    fn visit_set_expr(&self, _: &Rc<Expr>, expr: &SetExpr) -> Result<(), LoxResult> {
+        self.resolve_expr(&expr.value)?;
+        self.resolve_expr(&expr.object)?;
         Ok(())
     }
 
-    fn visit_get_expr(&self, _: Rc<Expr>, expr: &GetExpr) -> Result<(), LoxResult> {
-        self.resolve_expr(expr.object.clone())?;
+    fn visit_get_expr(&self, _: &Rc<Expr>, expr: &GetExpr) -> Result<(), LoxResult> {
+        self.resolve_expr(&expr.object)?;
         Ok(())
     }
 
-    fn visit_call_expr(&self, _: Rc<Expr>, expr: &CallExpr) -> Result<(), LoxResult> {
-        self.resolve_expr(expr.callee.clone())?;
+    fn visit_index_expr(&self, _: &Rc<Expr>, expr: &IndexExpr) -> Result<(), LoxResult> {
+        self.resolve_expr(&expr.object)?;
+        self.resolve_expr(&expr.index)?;
+        Ok(())
+    }
+
+    fn visit_call_expr(&self, _: &Rc<Expr>, expr: &CallExpr) -> Result<(), LoxResult> {
+        self.resolve_expr(&expr.callee)?;
 
         for argument in expr.arguments.iter() {
-            self.resolve_expr(argument.clone())?;
+            self.resolve_expr(argument)?;
         }
 
         Ok(())
     }
 
-    fn visit_logical_expr(&self, _: Rc<Expr>, expr: &LogicalExpr) -> Result<(), LoxResult> {
-        self.resolve_expr(expr.left.clone())?;
-        self.resolve_expr(expr.right.clone())?;
+    fn visit_logical_expr(&self, _: &Rc<Expr>, expr: &LogicalExpr) -> Result<(), LoxResult> {
+        self.resolve_expr(&expr.left)?;
+        self.resolve_expr(&expr.right)?;
         Ok(())
     }
 
-    fn visit_assign_expr(&self, wrapper: Rc<Expr>, expr: &AssignExpr) -> Result<(), LoxResult> {
-        self.resolve_expr(expr.value.clone())?;
-        self.resolve_local(wrapper, &expr.name);
+    fn visit_assign_expr(&self, _: &Rc<Expr>, expr: &AssignExpr) -> Result<(), LoxResult> {
+        self.resolve_expr(&expr.value)?;
+        if self.resolve_local(&expr.slot, &expr.name) == Some(Binding::Constant) {
+            self.error(
+                &expr.name,
+                &format!("Can't assign to constant '{}'.", expr.name.as_string()),
+            );
+        }
         Ok(())
     }
 
-    fn visit_literal_expr(&self, _: Rc<Expr>, _expr: &LiteralExpr) -> Result<(), LoxResult> {
+    fn visit_literal_expr(&self, _: &Rc<Expr>, _expr: &LiteralExpr) -> Result<(), LoxResult> {
         Ok(())
     }
 
-    fn visit_grouping_expr(&self, _: Rc<Expr>, expr: &GroupingExpr) -> Result<(), LoxResult> {
-        self.resolve_expr(expr.expression.clone())?;
+    fn visit_grouping_expr(&self, _: &Rc<Expr>, expr: &GroupingExpr) -> Result<(), LoxResult> {
+        self.resolve_expr(&expr.expression)?;
         Ok(())
     }
 
-    fn visit_binary_expr(&self, _: Rc<Expr>, expr: &BinaryExpr) -> Result<(), LoxResult> {
-        self.resolve_expr(expr.left.clone())?;
-        self.resolve_expr(expr.right.clone())?;
+    fn visit_binary_expr(&self, _: &Rc<Expr>, expr: &BinaryExpr) -> Result<(), LoxResult> {
+        self.resolve_expr(&expr.left)?;
+        self.resolve_expr(&expr.right)?;
         Ok(())
     }
 
-    fn visit_unary_expr(&self, _: Rc<Expr>, expr: &UnaryExpr) -> Result<(), LoxResult> {
-        self.resolve_expr(expr.right.clone())?;
+    fn visit_unary_expr(&self, _: &Rc<Expr>, expr: &UnaryExpr) -> Result<(), LoxResult> {
+        self.resolve_expr(&expr.right)?;
         Ok(())
     }
 
-    fn visit_variable_expr(&self, wrapper: Rc<Expr>, expr: &VariableExpr) -> Result<(), LoxResult> {
+    fn visit_variable_expr(&self, _: &Rc<Expr>, expr: &VariableExpr) -> Result<(), LoxResult> {
         if !self.scopes.borrow().is_empty()
             && self
                 .scopes
@@ -258,35 +392,36 @@ impl<'a> ExprVisitor<()> for Resolver<'a> {
                 .last()
                 .unwrap()
                 .borrow()
-                .get(&expr.name.as_string())
-                == Some(&false)
+                .get(&expr.name.lexeme())
+                .map(|local| local.binding)
+                == Some(Binding::Declared)
         {
             self.error(
                 &expr.name,
                 "Can't read local variable in its own initializer.",
             );
         } else {
-            self.resolve_local(wrapper, &expr.name);
+            self.resolve_local(&expr.slot, &expr.name);
         }
         Ok(())
     }
 }
 
-impl<'a> Resolver<'a> {
-    pub fn new(interpreter: &'a Interpreter) -> Self {
+impl Resolver {
+    pub fn new() -> Self {
         Self {
-            interpreter,
             scopes: RefCell::new(Vec::new()),
             had_error: RefCell::new(false),
             current_function: RefCell::new(FunctionType::None),
             current_class: RefCell::new(ClassType::None),
             in_while: RefCell::new(false),
+            global_traits: RefCell::new(HashMap::new()),
         }
     }
 
     pub fn resolve(&self, statements: &Rc<Vec<Rc<Stmt>>>) -> Result<(), LoxResult> {
         for statement in statements.deref() {
-            self.resolve_stmt(statement.clone())?;
+            self.resolve_stmt(statement)?;
         }
         Ok(())
     }
@@ -295,12 +430,12 @@ impl<'a> Resolver<'a> {
         !*self.had_error.borrow()
     }
 
-    fn resolve_stmt(&self, stmt: Rc<Stmt>) -> Result<(), LoxResult> {
-        stmt.accept(stmt.clone(), self)
+    fn resolve_stmt(&self, stmt: &Rc<Stmt>) -> Result<(), LoxResult> {
+        stmt.accept(stmt, self)
     }
 
-    fn resolve_expr(&self, expr: Rc<Expr>) -> Result<(), LoxResult> {
-        expr.accept(expr.clone(), self)
+    fn resolve_expr(&self, expr: &Rc<Expr>) -> Result<(), LoxResult> {
+        expr.accept(expr, self)
     }
 
     fn begin_scope(&self) {
@@ -313,29 +448,124 @@ impl<'a> Resolver<'a> {
 
     fn declare(&self, name: &Token) {
         if let Some(scope) = self.scopes.borrow().last() {
-            if scope.borrow().contains_key(&name.as_string()) {
+            if scope.borrow().contains_key(&name.lexeme()) {
                 self.error(name, "Already a variable with this name in this scope.");
             }
 
-            scope.borrow_mut().insert(name.as_string(), false);
+            let slot = scope.borrow().len();
+            scope.borrow_mut().insert(
+                name.lexeme(),
+                Local {
+                    binding: Binding::Declared,
+                    slot,
+                    trait_methods: None,
+                },
+            );
+        } else {
+            self.global_traits.borrow_mut().remove(&name.lexeme());
         }
     }
 
     fn define(&self, name: &Token) {
+        self.define_as(name, Binding::Variable);
+    }
+
+    fn define_as(&self, name: &Token, binding: Binding) {
         if let Some(scope) = self.scopes.borrow().last() {
-            scope.borrow_mut().insert(name.as_string(), true);
+            if let Some(local) = scope.borrow_mut().get_mut(&name.lexeme()) {
+                local.binding = binding;
+            }
         }
     }
 
-    fn resolve_local(&self, expr: Rc<Expr>, name: &Token) {
-        for (scope, map) in self.scopes.borrow().iter().rev().enumerate() {
-            if map.borrow().contains_key(&name.as_string()) {
-                self.interpreter.resolve(expr, scope);
-                return;
+    // Binds `this` or `super` as the only name in a fresh scope
+    fn define_keyword(&self, name: Symbol) {
+        if let Some(scope) = self.scopes.borrow().last() {
+            scope.borrow_mut().insert(
+                name,
+                Local {
+                    binding: Binding::Variable,
+                    slot: 0,
+                    trait_methods: None,
+                },
+            );
+        }
+    }
+
+    fn define_trait(&self, name: &Token, methods: Rc<[Symbol]>) {
+        if let Some(scope) = self.scopes.borrow().last() {
+            if let Some(local) = scope.borrow_mut().get_mut(&name.lexeme()) {
+                local.trait_methods = Some(methods);
             }
+        } else {
+            self.global_traits
+                .borrow_mut()
+                .insert(name.lexeme(), methods);
         }
     }
 
+    // The methods of the trait a name refers to, found the way the name
+    // itself resolves. None when it isn't bound to a trait declaration.
+    fn trait_methods(&self, name: &Token) -> Option<Rc<[Symbol]>> {
+        for map in self.scopes.borrow().iter().rev() {
+            if let Some(local) = map.borrow().get(&name.lexeme()) {
+                return local.trait_methods.clone();
+            }
+        }
+        self.global_traits.borrow().get(&name.lexeme()).cloned()
+    }
+
+    // Two traits may not provide the same method unless the class overrides
+    // it. Traits reached through other variables are checked at runtime.
+    fn check_trait_conflicts(&self, stmt: &ClassStmt) {
+        let own_methods: Vec<Symbol> = stmt
+            .methods
+            .iter()
+            .filter_map(|m| match m.deref() {
+                Stmt::Function(f) => Some(f.name.lexeme()),
+                _ => None,
+            })
+            .collect();
+
+        let mut provided: HashMap<Symbol, Symbol> = HashMap::new();
+        for t in stmt.traits.iter() {
+            let Expr::Variable(v) = t.deref() else {
+                continue;
+            };
+            let trait_name = v.name.lexeme();
+            for method in self.trait_methods(&v.name).iter().flat_map(|m| m.iter()) {
+                if own_methods.contains(method) {
+                    continue;
+                }
+                if let Some(other) = provided.get(method) {
+                    self.error(
+                        &v.name,
+                        &format!(
+                            "Method '{method}' from trait '{trait_name}' conflicts with trait '{other}'."
+                        ),
+                    );
+                } else {
+                    provided.insert(*method, trait_name);
+                }
+            }
+        }
+    }
+
+    // Records where the name lives on the node that uses it, and returns how
+    // it was bound. Globals are left unresolved.
+    fn resolve_local(&self, slot: &Cell<Option<Slot>>, name: &Token) -> Option<Binding> {
+        for (depth, map) in self.scopes.borrow().iter().rev().enumerate() {
+            if let Some(local) = map.borrow().get(&name.lexeme()) {
+                slot.set(Some(Slot {
+                    depth,
+                    index: local.slot,
+                }));
+                return Some(local.binding);
+            }
+        }
+        None
+    }
+
     fn resolve_function(
         &self,
         function: &FunctionStmt,
@@ -345,10 +575,17 @@ impl<'a> Resolver<'a> {
 
         self.begin_scope();
 
-        for param in function.params.iter() {
+        for (param, default) in function.params.iter().zip(function.defaults.iter()) {
+            if let Some(default) = default {
+                self.resolve_expr(default)?;
+            }
             self.declare(param);
             self.define(param);
         }
+        if let Some(rest) = &function.rest {
+            self.declare(rest);
+            self.define(rest);
+        }
 
         self.resolve(&function.body)?;
 
@@ -358,6 +595,15 @@ impl<'a> Resolver<'a> {
         Ok(())
     }
 
+    fn warning(&self, token: &Token, message: &str) {
+        eprintln!(
+            "[line {}] Warning at '{}': {}",
+            token.line,
+            token.as_string(),
+            message
+        );
+    }
+
     fn error(&self, token: &Token, message: &str) {
         self.had_error.replace(true);
         LoxResult::parse_error(token, message);
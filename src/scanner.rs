@@ -55,8 +55,19 @@ impl Scanner {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
+            ':' => self.add_token(TokenType::Colon),
+            '.' => {
+                if self.peek() == Some('.') && self.peek_next() == Some('.') {
+                    self.advance();
+                    self.advance();
+                    self.add_token(TokenType::Ellipsis);
+                } else {
+                    self.add_token(TokenType::Dot);
+                }
+            }
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::SemiColon),
@@ -72,6 +83,8 @@ impl Scanner {
             '=' => {
                 let tok = if self.is_match('=') {
                     TokenType::Equals
+                } else if self.is_match('>') {
+                    TokenType::Arrow
                 } else {
                     TokenType::Assign
                 };
@@ -248,7 +261,7 @@ impl Scanner {
     fn add_token_object(&mut self, ttype: TokenType, literal: Option<Object>) {
         let lexeme: String = self.source[self.start..self.current].iter().collect();
         self.tokens
-            .push(Token::new(ttype, lexeme, literal, self.line));
+            .push(Token::new(ttype, &lexeme, literal, self.line));
     }
 
     fn is_match(&mut self, expected: char) -> bool {
@@ -273,20 +286,27 @@ impl Scanner {
         match check {
             "and" => Some(TokenType::And),
             "class" => Some(TokenType::Class),
+            "do" => Some(TokenType::Do),
             "else" => Some(TokenType::Else),
             "false" => Some(TokenType::False),
             "for" => Some(TokenType::For),
             "fun" => Some(TokenType::Fun),
             "if" => Some(TokenType::If),
+            "in" => Some(TokenType::In),
+            "is" => Some(TokenType::Is),
+            "match" => Some(TokenType::Match),
             "nil" => Some(TokenType::Nil),
             "or" => Some(TokenType::Or),
             "print" => Some(TokenType::Print),
             "return" => Some(TokenType::Return),
             "super" => Some(TokenType::Super),
             "this" => Some(TokenType::This),
+            "trait" => Some(TokenType::Trait),
             "true" => Some(TokenType::True),
             "var" => Some(TokenType::Var),
+            "const" => Some(TokenType::Const),
             "while" => Some(TokenType::While),
+            "with" => Some(TokenType::With),
             "break" => Some(TokenType::Break),
             _ => None,
         }
@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+// An interned string. Every copy of the same text is the same symbol, so
+// symbols are copied, compared and hashed as plain numbers. Interned text
+// lives for the rest of the program.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// Names the interpreter itself looks up, interned up front in this order so
+// they can be constants
+const PREDEFINED: [&str; 4] = ["init", "this", "super", "toString"];
+
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner {
+        symbols: PREDEFINED
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (*name, Symbol(i as u32)))
+            .collect(),
+        names: PREDEFINED.to_vec(),
+    });
+}
+
+impl Symbol {
+    pub const INIT: Symbol = Symbol(0);
+    pub const THIS: Symbol = Symbol(1);
+    pub const SUPER: Symbol = Symbol(2);
+    pub const TO_STRING: Symbol = Symbol(3);
+
+    pub fn intern(name: &str) -> Symbol {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            if let Some(symbol) = interner.symbols.get(name) {
+                return *symbol;
+            }
+            let name: &'static str = Box::leak(name.into());
+            let symbol = Symbol(interner.names.len() as u32);
+            interner.names.push(name);
+            interner.symbols.insert(name, symbol);
+            symbol
+        })
+    }
+
+    // The symbol for `name` if it has been interned, without interning it.
+    // Text that comes from running code should be looked up this way, since
+    // interning it would keep it alive forever.
+    pub fn lookup(name: &str) -> Option<Symbol> {
+        INTERNER.with(|interner| interner.borrow().symbols.get(name).copied())
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize])
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_text_gives_the_same_symbol() {
+        let a = Symbol::intern("name");
+        let b = Symbol::intern(&String::from("name"));
+        assert_eq!(a, b);
+        assert_ne!(a, Symbol::intern("other"));
+        assert_eq!(a.as_str(), "name");
+    }
+
+    #[test]
+    fn predefined_symbols_match_their_text() {
+        assert_eq!(Symbol::intern("init"), Symbol::INIT);
+        assert_eq!(Symbol::intern("toString"), Symbol::TO_STRING);
+        assert_eq!(Symbol::SUPER.as_str(), "super");
+    }
+
+    #[test]
+    fn lookup_does_not_intern() {
+        assert_eq!(Symbol::lookup("never interned"), None);
+        assert_eq!(Symbol::lookup("never interned"), None);
+        let symbol = Symbol::intern("looked up");
+        assert_eq!(Symbol::lookup("looked up"), Some(symbol));
+    }
+}
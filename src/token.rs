@@ -1,17 +1,25 @@
 use crate::object::Object;
+use crate::symbol::*;
 use crate::token_type::*;
 use std::fmt;
 
 #[derive(Debug)]
 pub struct Token {
     ttype: TokenType,
-    lexeme: String,
+    // Interned, except for string and number literals: their value is in
+    // `literal`, and interning every distinct one would keep it alive for
+    // the rest of the program
+    lexeme: Option<Symbol>,
     pub literal: Option<Object>,
     pub line: usize,
 }
 
 impl Token {
-    pub fn new(ttype: TokenType, lexeme: String, literal: Option<Object>, line: usize) -> Token {
+    pub fn new(ttype: TokenType, lexeme: &str, literal: Option<Object>, line: usize) -> Token {
+        let lexeme = match ttype {
+            TokenType::String | TokenType::Number => None,
+            _ => Some(Symbol::intern(lexeme)),
+        };
         Token {
             ttype,
             lexeme,
@@ -28,14 +36,23 @@ impl Token {
         self.ttype
     }
 
+    pub fn lexeme(&self) -> Symbol {
+        self.lexeme.expect("literal tokens have no interned lexeme")
+    }
+
     pub fn as_string(&self) -> String {
-        self.lexeme.clone()
+        match (self.lexeme, &self.literal) {
+            (Some(lexeme), _) => lexeme.to_string(),
+            (None, Some(Object::Str(s))) => format!("\"{s}\""),
+            (None, Some(literal)) => literal.to_string(),
+            (None, None) => String::new(),
+        }
     }
 
     pub fn dup(&self) -> Token {
         Token {
             ttype: self.ttype,
-            lexeme: self.lexeme.to_string(),
+            lexeme: self.lexeme,
             literal: self.literal.clone(),
             line: self.line,
         }
@@ -44,7 +61,7 @@ impl Token {
     pub fn eof(line: usize) -> Token {
         Token {
             ttype: TokenType::Eof,
-            lexeme: "".to_string(),
+            lexeme: Some(Symbol::intern("")),
             literal: None,
             line,
         }
@@ -57,7 +74,7 @@ impl fmt::Display for Token {
             f,
             "{:?} {} {}",
             self.ttype,
-            self.lexeme,
+            self.as_string(),
             if let Some(literal) = &self.literal {
                 literal.to_string()
             } else {
@@ -74,3 +91,21 @@ pub enum Token {
     Keyword { lexeme: String, ttype: String, },
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals_keep_their_text_without_interning_it() {
+        let name = Token::new(TokenType::Identifier, "name", None, 1);
+        assert_eq!(name.lexeme(), Symbol::intern("name"));
+
+        let text = Some(Object::Str("some text".to_string()));
+        let string = Token::new(TokenType::String, "\"some text\"", text, 1);
+        let number = Token::new(TokenType::Number, "12.5", Some(Object::Num(12.5)), 1);
+        assert!(string.lexeme.is_none() && number.lexeme.is_none());
+        assert_eq!(string.as_string(), "\"some text\"");
+        assert_eq!(number.as_string(), "12.5");
+    }
+}
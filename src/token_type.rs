@@ -5,8 +5,12 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
+    Ellipsis,
     Minus,
     Plus,
     SemiColon,
@@ -15,6 +19,7 @@ pub enum TokenType {
     Bang,
     BangEqual,
     Assign, // ('=')
+    Arrow,  // ('=>')
     Equals, // ('==')
     Greater,
     GreaterEqual,
@@ -25,19 +30,26 @@ pub enum TokenType {
     Number,
     And,
     Class,
+    Do,
     Else,
     False,
     Fun,
     For,
     If,
+    In,
+    Is,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Trait,
     True,
     Var,
+    Const,
     While,
+    With,
     Eof,
 }
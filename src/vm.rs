@@ -0,0 +1,735 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use crate::callable::*;
+use crate::chunk::*;
+use crate::compiler::*;
+use crate::error::*;
+use crate::gc::*;
+use crate::interpreter::*;
+use crate::lox_class::*;
+use crate::lox_function::*;
+use crate::lox_trait::*;
+use crate::object::*;
+use crate::stmt::*;
+use crate::symbol::*;
+use crate::token::*;
+
+// A captured variable. It points at its stack slot while the function that
+// declared it is running and holds the value itself once that slot is gone.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Object),
+}
+
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Trace for Upvalue {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        if let Upvalue::Closed(value) = self {
+            edges.extend(Node::of(value));
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        edges.extend(self.upvalues.iter().map(|u| Node::Upvalue(Rc::clone(u))));
+    }
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // Stack index of slot 0
+    base: usize,
+    // How many arguments the caller passed by position, or which parameters
+    // it gave when some were passed by name
+    argc: usize,
+    provided: Option<Vec<bool>>,
+    iterators: usize,
+}
+
+impl CallFrame {
+    fn is_provided(&self, param: usize) -> bool {
+        match &self.provided {
+            Some(provided) => provided[param],
+            None => param < self.argc,
+        }
+    }
+}
+
+// Runs compiled code on a value stack. Values, classes and instances are the
+// same as the tree-walker's, and the interpreter is kept around to hold the
+// globals and to perform anything that is not worth a fast path here.
+pub struct Vm {
+    interpreter: Interpreter,
+    stack: RefCell<Vec<Object>>,
+    frames: RefCell<Vec<CallFrame>>,
+    open_upvalues: RefCell<Vec<Rc<RefCell<Upvalue>>>>,
+    iterators: RefCell<Vec<Iteration>>,
+    me: Weak<Vm>,
+}
+
+macro_rules! arithmetic {
+    ($vm:expr, $frame:expr, $token:expr, $result:ident, $op:tt) => {{
+        let right = $vm.pop();
+        let left = $vm.pop();
+        let value = match (left, right) {
+            (Object::Num(left), Object::Num(right)) => Object::$result(left $op right),
+            (left, right) => $vm.interpreter.binary(left, $vm.token(&$frame, $token), right)?,
+        };
+        $vm.push(value);
+    }};
+}
+
+impl Vm {
+    pub fn new() -> Rc<Vm> {
+        Rc::new_cyclic(|me| Vm {
+            interpreter: Interpreter::new(),
+            stack: RefCell::new(Vec::new()),
+            frames: RefCell::new(Vec::new()),
+            open_upvalues: RefCell::new(Vec::new()),
+            iterators: RefCell::new(Vec::new()),
+            me: me.clone(),
+        })
+    }
+
+    pub fn interpret(&self, statements: &[Rc<Stmt>]) -> Result<(), LoxResult> {
+        let function = Compiler::new().compile(statements)?;
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: Vec::new(),
+        });
+
+        let base = self.stack.borrow().len();
+        let iterators = self.iterators.borrow().len();
+        self.push(Object::Nil);
+        let result = self.run(CallFrame {
+            closure,
+            ip: 0,
+            base,
+            argc: 0,
+            provided: None,
+            iterators,
+        });
+
+        if result.is_err() {
+            self.stack.borrow_mut().clear();
+            self.frames.borrow_mut().clear();
+            self.open_upvalues.borrow_mut().clear();
+            self.iterators.borrow_mut().clear();
+        }
+        result.map(|_| ())
+    }
+
+    pub fn print_environment(&self) {
+        println!("{:?}", self.interpreter.globals);
+    }
+
+    // Calls a compiled function from outside the run loop, as natives and
+    // property hooks do. `slots` has one entry per parameter, with None for
+    // those the caller left out.
+    pub fn call_function(
+        &self,
+        closure: &Rc<Closure>,
+        receiver: Option<Object>,
+        slots: Vec<Option<Object>>,
+        rest: Vec<Object>,
+    ) -> Result<Object, LoxResult> {
+        let base = self.stack.borrow().len();
+        self.push(receiver.unwrap_or(Object::Nil));
+
+        let provided = slots.iter().map(Option::is_some).collect();
+        for slot in slots {
+            self.push(slot.unwrap_or(Object::Nil));
+        }
+        if closure.function.rest.is_some() {
+            self.push(Object::list(rest));
+        }
+
+        let iterators = self.iterators.borrow().len();
+        self.run(CallFrame {
+            closure: Rc::clone(closure),
+            ip: 0,
+            base,
+            argc: 0,
+            provided: Some(provided),
+            iterators,
+        })
+    }
+
+    // Runs until `frame` returns. Calls between compiled functions push a
+    // frame here instead of recursing, so only calls that go through the
+    // interpreter (natives, hooks, getters) nest run loops.
+    fn run(&self, mut frame: CallFrame) -> Result<Object, LoxResult> {
+        let entry = self.frames.borrow().len();
+
+        loop {
+            let op = frame.closure.function.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Constant(index) => {
+                    let value = frame.closure.function.chunk.constants[index as usize].clone();
+                    self.push(value);
+                }
+                Op::Nil => self.push(Object::Nil),
+                Op::True => self.push(Object::Bool(true)),
+                Op::False => self.push(Object::Bool(false)),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::GetLocal(slot) => {
+                    let value = self.stack.borrow()[frame.base + slot as usize].clone();
+                    self.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let value = self.peek(0);
+                    self.stack.borrow_mut()[frame.base + slot as usize] = value;
+                }
+                Op::GetUpvalue(index) => {
+                    let value = match &*frame.closure.upvalues[index as usize].borrow() {
+                        Upvalue::Open(slot) => self.stack.borrow()[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.push(value);
+                }
+                Op::SetUpvalue(index) => {
+                    let value = self.peek(0);
+                    let mut upvalue = frame.closure.upvalues[index as usize].borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack.borrow_mut()[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                Op::GetGlobal(name) => {
+                    let value = self
+                        .interpreter
+                        .globals
+                        .borrow()
+                        .get(self.token(&frame, name))?;
+                    self.push(value);
+                }
+                Op::SetGlobal(name) => {
+                    let value = self.peek(0);
+                    self.interpreter
+                        .globals
+                        .borrow_mut()
+                        .assign(self.token(&frame, name), value)?;
+                }
+                Op::DefineGlobal(name) => {
+                    let value = self.pop();
+                    let name = self.token(&frame, name).lexeme();
+                    self.interpreter.globals.borrow_mut().define(name, value);
+                }
+                Op::DefineConstant(name) => {
+                    let value = self.pop();
+                    let name = self.token(&frame, name).lexeme();
+                    self.interpreter
+                        .globals
+                        .borrow_mut()
+                        .define_constant(name, value);
+                }
+                Op::GetProperty(name, cache) => {
+                    let object = self.pop();
+                    let value = self.interpreter.get_property(
+                        object,
+                        self.token(&frame, name),
+                        Some(&frame.closure.function.chunk.caches[cache as usize]),
+                    )?;
+                    self.push(value);
+                }
+                Op::SetProperty(name, cache) => {
+                    let value = self.pop();
+                    let object = self.pop();
+                    self.interpreter.set_property(
+                        object,
+                        self.token(&frame, name),
+                        value.clone(),
+                        Some(&frame.closure.function.chunk.caches[cache as usize]),
+                    )?;
+                    self.push(value);
+                }
+                Op::GetMethod(name, cache) => {
+                    let object = self.pop();
+                    let name = self.token(&frame, name);
+                    let cache = &frame.closure.function.chunk.caches[cache as usize];
+                    let method = match &object {
+                        Object::Instance(inst) => inst.method(name, cache),
+                        _ => None,
+                    };
+                    if let Some(method) = method {
+                        self.push(Object::Func(method));
+                        self.push(object);
+                    } else {
+                        let value = self.interpreter.get_property(object, name, Some(cache))?;
+                        self.push(value);
+                        self.push(Object::Nil);
+                    }
+                }
+                Op::GetSuper(method) => {
+                    let object = self.pop();
+                    let superclass = if let Object::Class(superclass) = self.pop() {
+                        superclass
+                    } else {
+                        panic!("Unable to extract superclass");
+                    };
+                    let value = self.interpreter.super_method(
+                        &superclass,
+                        object,
+                        self.token(&frame, method),
+                    )?;
+                    self.push(value);
+                }
+                Op::Index(bracket) => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let value =
+                        self.interpreter
+                            .index(object, index, self.token(&frame, bracket))?;
+                    self.push(value);
+                }
+                Op::Equal(operator) | Op::NotEqual(operator) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = match (&left, &right) {
+                        (Object::Instance(_), _) | (_, Object::Instance(_)) => self
+                            .interpreter
+                            .binary(left, self.token(&frame, operator), right)?,
+                        _ => Object::Bool(left.is_equal(&right) == matches!(op, Op::Equal(_))),
+                    };
+                    self.push(value);
+                }
+                Op::Greater(operator) => arithmetic!(self, frame, operator, Bool, >),
+                Op::GreaterEqual(operator) => arithmetic!(self, frame, operator, Bool, >=),
+                Op::Less(operator) => arithmetic!(self, frame, operator, Bool, <),
+                Op::LessEqual(operator) => arithmetic!(self, frame, operator, Bool, <=),
+                Op::Add(operator) => arithmetic!(self, frame, operator, Num, +),
+                Op::Subtract(operator) => arithmetic!(self, frame, operator, Num, -),
+                Op::Multiply(operator) => arithmetic!(self, frame, operator, Num, *),
+                Op::Divide(operator) => arithmetic!(self, frame, operator, Num, /),
+                Op::Is(operator) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value =
+                        self.interpreter
+                            .binary(left, self.token(&frame, operator), right)?;
+                    self.push(value);
+                }
+                Op::Not => {
+                    let value = self.pop();
+                    self.push(Object::Bool(!self.interpreter.is_truthy(&value)));
+                }
+                Op::Negate(operator) => {
+                    let value = match self.pop() {
+                        Object::Num(n) => Object::Num(-n),
+                        value => self
+                            .interpreter
+                            .unary(self.token(&frame, operator), value)?,
+                    };
+                    self.push(value);
+                }
+                Op::Same => {
+                    let pattern = self.pop();
+                    let value = self.pop();
+                    self.push(Object::Bool(value.is_equal(&pattern)));
+                }
+                Op::Print(keyword) => {
+                    let value = self.pop();
+                    let text = self
+                        .interpreter
+                        .stringify(&value, self.token(&frame, keyword))?;
+                    println!("{text}");
+                }
+                Op::Jump(offset) => frame.ip += offset as usize,
+                Op::JumpIfFalse(offset) => {
+                    if !self.interpreter.is_truthy(&self.stack.borrow()[self.top()]) {
+                        frame.ip += offset as usize;
+                    }
+                }
+                Op::Loop(offset) => frame.ip -= offset as usize,
+                Op::JumpIfProvided(param, offset) => {
+                    if frame.is_provided(param as usize) {
+                        frame.ip += offset as usize;
+                    }
+                }
+                Op::Call(argc, paren) | Op::TailCall(argc, paren) => {
+                    let argc = argc as usize;
+                    if let Some(callee) = self.call_frame(&frame, argc, paren, None)? {
+                        let tail = matches!(op, Op::TailCall(..));
+                        frame = self.enter(frame, callee, tail);
+                    } else {
+                        let arguments = self.pop_many(argc);
+                        let callee = self.pop();
+                        let value =
+                            self.interpreter
+                                .call(callee, arguments, self.token(&frame, paren))?;
+                        self.push(value);
+                    }
+                }
+                Op::Invoke(argc, paren) => {
+                    let argc = argc as usize;
+                    let at = self.top() - argc;
+                    let receiver = match self.stack.borrow_mut().remove(at) {
+                        Object::Nil => None,
+                        receiver => Some(receiver),
+                    };
+                    if let Some(callee) = self.call_frame(&frame, argc, paren, receiver.clone())? {
+                        self.frames.borrow_mut().push(frame);
+                        frame = callee;
+                    } else {
+                        let arguments = self.pop_many(argc);
+                        let callee = match (self.pop(), &receiver) {
+                            (Object::Func(method), Some(receiver)) => method.bind(receiver),
+                            (callee, _) => callee,
+                        };
+                        let value =
+                            self.interpreter
+                                .call(callee, arguments, self.token(&frame, paren))?;
+                        self.push(value);
+                    }
+                }
+                Op::CallNamed(names, paren) | Op::TailCallNamed(names, paren) => {
+                    let names = &frame.closure.function.chunk.names[names as usize];
+                    let values = self.pop_many(names.len());
+
+                    let mut arguments = Vec::new();
+                    let mut named = Vec::new();
+                    for (value, name) in values.into_iter().zip(names.iter()) {
+                        if let Some(name) = name {
+                            named.push((name.dup(), value));
+                        } else {
+                            arguments.push(value);
+                        }
+                    }
+
+                    let paren = self.token(&frame, paren);
+                    if let Some((function, closure, receiver)) = self.compiled(&self.peek(0), None)
+                    {
+                        let (slots, rest) = function.bind_arguments(arguments, named, paren)?;
+                        let callee = self.named_frame(closure, receiver, slots, rest);
+                        let tail = matches!(op, Op::TailCallNamed(..));
+                        frame = self.enter(frame, callee, tail);
+                    } else {
+                        let callee = self.pop();
+                        let value = self
+                            .interpreter
+                            .call_named(callee, arguments, named, paren)?;
+                        self.push(value);
+                    }
+                }
+                Op::Closure(index) => {
+                    let function =
+                        Rc::clone(&frame.closure.function.chunk.functions[index as usize]);
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|upvalue| {
+                            if upvalue.is_local {
+                                self.capture_upvalue(frame.base + upvalue.index)
+                            } else {
+                                Rc::clone(&frame.closure.upvalues[upvalue.index])
+                            }
+                        })
+                        .collect();
+                    let closure = Rc::new(Closure { function, upvalues });
+                    self.push(Object::Func(Rc::new(LoxFunction::compiled(
+                        closure,
+                        self.me.clone(),
+                    ))));
+                }
+                Op::CloseUpvalue => {
+                    self.close_upvalues(self.top());
+                    self.pop();
+                }
+                Op::Return => {
+                    let value = self.pop();
+                    self.close_upvalues(frame.base);
+                    self.stack.borrow_mut().truncate(frame.base);
+                    self.iterators.borrow_mut().truncate(frame.iterators);
+
+                    if self.frames.borrow().len() == entry {
+                        return Ok(value);
+                    }
+                    frame = self.frames.borrow_mut().pop().unwrap();
+                    self.push(value);
+                }
+                Op::Inherit(name) => {
+                    self.interpreter
+                        .as_superclass(self.peek(0), self.token(&frame, name))?;
+                }
+                Op::Class(index) => {
+                    let value =
+                        self.class(&frame.closure.function.chunk.classes[index as usize])?;
+                    self.push(value);
+                }
+                Op::Trait(index) => {
+                    let info = &frame.closure.function.chunk.classes[index as usize];
+                    let methods = self.pop_many(info.methods.len());
+                    let methods = info.methods.iter().cloned().zip(methods).collect();
+                    let t = LoxTrait::new(&info.name.as_string(), methods);
+                    self.push(Object::Trait(Rc::new(t)));
+                }
+                Op::IterStart(name) => {
+                    let iterable = self.pop();
+                    let iteration = self
+                        .interpreter
+                        .iterate(iterable, self.token(&frame, name))?;
+                    self.iterators.borrow_mut().push(iteration);
+                }
+                Op::IterNext(name, offset) => {
+                    // Taken off the iterator stack while next() runs, since
+                    // that may run a loop of its own
+                    let mut iteration = self.iterators.borrow_mut().pop().unwrap();
+                    let next = self
+                        .interpreter
+                        .next_item(&mut iteration, self.token(&frame, name));
+                    self.iterators.borrow_mut().push(iteration);
+                    match next? {
+                        Some(value) => self.push(value),
+                        None => frame.ip += offset as usize,
+                    }
+                }
+                Op::IterEnd => {
+                    self.iterators.borrow_mut().pop();
+                }
+                Op::Fail(token) => {
+                    return Err(LoxResult::runtime_error(
+                        self.token(&frame, token),
+                        "Can't run code that failed to parse.",
+                    ));
+                }
+            }
+        }
+    }
+
+    // Sets up a frame for calling a compiled function, or a class with a
+    // compiled initializer, that sits below its `argc` arguments on the
+    // stack. A method found by GetMethod comes with its receiver. Anything
+    // else is called through the interpreter.
+    fn call_frame(
+        &self,
+        frame: &CallFrame,
+        argc: usize,
+        paren: u32,
+        receiver: Option<Object>,
+    ) -> Result<Option<CallFrame>, LoxResult> {
+        let base = self.stack.borrow().len() - argc - 1;
+        let callee = self.stack.borrow()[base].clone();
+        let Some((function, closure, receiver)) = self.compiled(&callee, receiver) else {
+            return Ok(None);
+        };
+
+        let arity = callee_arity(&callee, &function);
+        if !arity.accepts(argc) {
+            return Err(LoxResult::runtime_error(
+                self.token(frame, paren),
+                &format!("Expected {arity} arguments but got {argc}."),
+            ));
+        }
+
+        if let Some(receiver) = receiver {
+            self.stack.borrow_mut()[base] = receiver;
+        }
+
+        let params = closure.function.params.len();
+        if argc > params {
+            let rest = self.pop_many(argc - params);
+            self.push(Object::list(rest));
+        } else {
+            for _ in argc..params {
+                self.push(Object::Nil);
+            }
+            if closure.function.rest.is_some() {
+                self.push(Object::list(Vec::new()));
+            }
+        }
+
+        Ok(Some(CallFrame {
+            closure,
+            ip: 0,
+            base,
+            argc,
+            provided: None,
+            iterators: self.iterators.borrow().len(),
+        }))
+    }
+
+    // The function, closure and receiver to run for a call to `callee` when
+    // it is a compiled function of this VM or a class whose initializer is
+    fn compiled(
+        &self,
+        callee: &Object,
+        receiver: Option<Object>,
+    ) -> Option<(Rc<LoxFunction>, Rc<Closure>, Option<Object>)> {
+        let (function, receiver) = match callee {
+            Object::Func(function) => (Rc::clone(function), receiver),
+            Object::Class(klass) => match klass.find_method(Symbol::INIT) {
+                Some(Object::Func(init)) => (init, Some(Object::instance(Rc::clone(klass)))),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        let (closure, receiver) = match function.body() {
+            Body::Compiled {
+                closure,
+                receiver: bound,
+                vm,
+            } if Weak::ptr_eq(vm, &self.me) => (Rc::clone(closure), receiver.or(bound.clone())),
+            _ => return None,
+        };
+        Some((function, closure, receiver))
+    }
+
+    // Sets up a frame for a call whose arguments were matched to the
+    // parameters by name. The callee is on top of the stack, and the slots
+    // go above it as call_function() lays them out.
+    fn named_frame(
+        &self,
+        closure: Rc<Closure>,
+        receiver: Option<Object>,
+        slots: Vec<Option<Object>>,
+        rest: Vec<Object>,
+    ) -> CallFrame {
+        let base = self.top();
+        if let Some(receiver) = receiver {
+            self.stack.borrow_mut()[base] = receiver;
+        }
+
+        let provided = slots.iter().map(Option::is_some).collect();
+        for slot in slots {
+            self.push(slot.unwrap_or(Object::Nil));
+        }
+        if closure.function.rest.is_some() {
+            self.push(Object::list(rest));
+        }
+
+        CallFrame {
+            closure,
+            ip: 0,
+            base,
+            argc: 0,
+            provided: Some(provided),
+            iterators: self.iterators.borrow().len(),
+        }
+    }
+
+    // Makes `callee` the running frame. A tail call slides the callee and
+    // its arguments down over the caller's slots and runs it in their place.
+    fn enter(&self, frame: CallFrame, mut callee: CallFrame, tail: bool) -> CallFrame {
+        if tail {
+            self.close_upvalues(frame.base);
+            let mut stack = self.stack.borrow_mut();
+            let values = stack.split_off(callee.base);
+            stack.truncate(frame.base);
+            stack.extend(values);
+            self.iterators.borrow_mut().truncate(frame.iterators);
+            callee.base = frame.base;
+            callee.iterators = frame.iterators;
+        } else {
+            self.frames.borrow_mut().push(frame);
+        }
+        callee
+    }
+
+    // Builds a class from the superclass, traits and method closures the
+    // compiler left on the stack. The superclass stays there since it is
+    // the local the methods see as `super`.
+    fn class(&self, info: &ClassInfo) -> Result<Object, LoxResult> {
+        let setters = self.pop_many(info.setters.len());
+        let class_methods = self.pop_many(info.class_methods.len());
+        let methods = self.pop_many(info.methods.len());
+        let traits = self.pop_many(info.traits.len());
+        let superclass = if info.has_superclass {
+            match self.peek(0) {
+                Object::Class(superclass) => Some(superclass),
+                _ => panic!("superclass was not checked"),
+            }
+        } else {
+            None
+        };
+
+        let traits = info.traits.iter().map(|t| t.dup()).zip(traits).collect();
+        let mut table = self.interpreter.trait_methods(traits, &info.methods)?;
+        table.extend(info.methods.iter().cloned().zip(methods));
+
+        Ok(Object::class(LoxClass::new(
+            &info.name.as_string(),
+            superclass,
+            table,
+            info.class_methods
+                .iter()
+                .cloned()
+                .zip(class_methods)
+                .collect(),
+            info.setters.iter().cloned().zip(setters).collect(),
+        )))
+    }
+
+    fn capture_upvalue(&self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let mut open = self.open_upvalues.borrow_mut();
+        for upvalue in open.iter() {
+            if matches!(&*upvalue.borrow(), Upvalue::Open(s) if *s == slot) {
+                return Rc::clone(upvalue);
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        open.push(Rc::clone(&upvalue));
+        drop(open);
+        track(Node::Upvalue(Rc::clone(&upvalue)));
+        upvalue
+    }
+
+    // Moves the values of captured slots at or above `from` off the stack
+    fn close_upvalues(&self, from: usize) {
+        let stack = self.stack.borrow();
+        self.open_upvalues.borrow_mut().retain(|upvalue| {
+            let slot = match &*upvalue.borrow() {
+                Upvalue::Open(slot) if *slot >= from => *slot,
+                _ => return true,
+            };
+            upvalue.replace(Upvalue::Closed(stack[slot].clone()));
+            false
+        });
+    }
+
+    fn token<'a>(&self, frame: &'a CallFrame, index: u32) -> &'a Token {
+        &frame.closure.function.chunk.tokens[index as usize]
+    }
+
+    fn push(&self, value: Object) {
+        self.stack.borrow_mut().push(value);
+    }
+
+    fn pop(&self) -> Object {
+        self.stack.borrow_mut().pop().unwrap()
+    }
+
+    fn pop_many(&self, count: usize) -> Vec<Object> {
+        let mut stack = self.stack.borrow_mut();
+        let at = stack.len() - count;
+        stack.split_off(at)
+    }
+
+    fn peek(&self, distance: usize) -> Object {
+        let stack = self.stack.borrow();
+        stack[stack.len() - 1 - distance].clone()
+    }
+
+    fn top(&self) -> usize {
+        self.stack.borrow().len() - 1
+    }
+}
+
+fn callee_arity(callee: &Object, function: &Rc<LoxFunction>) -> Arity {
+    if let Object::Class(klass) = callee {
+        klass.arity()
+    } else {
+        function.arity()
+    }
+}
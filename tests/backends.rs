@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Runs every script in tests/lox on both backends, with and without the
+// optimizer, and compares the result with the script's .out file: stdout,
+// then each stderr line prefixed with "[stderr] ", then the exit code. Set
+// BLESS=1 to rewrite the .out files from the tree-walker.
+
+fn run(args: &[&str], script: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_lox-ast"))
+        .args(args)
+        .arg(script)
+        .output()
+        .expect("could not run lox-ast");
+
+    let mut result = String::from_utf8_lossy(&output.stdout).into_owned();
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        result.push_str(&format!("[stderr] {line}\n"));
+    }
+    result.push_str(&format!("exit: {}\n", output.status.code().unwrap_or(-1)));
+    result
+}
+
+fn scripts() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let mut scripts: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("could not read tests/lox")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+#[test]
+fn both_backends_match_the_expected_output() {
+    let mut failures = Vec::new();
+    for script in scripts() {
+        if std::env::var_os("BLESS").is_some() {
+            fs::write(script.with_extension("out"), run(&[], &script)).unwrap();
+        }
+        let expected = fs::read_to_string(script.with_extension("out")).unwrap_or_default();
+        let backends = [
+            ("tree", &[][..]),
+            ("vm", &["--vm"][..]),
+            ("tree, optimized", &["--optimize"][..]),
+            ("vm, optimized", &["--vm", "--optimize"][..]),
+        ];
+        for (backend, args) in backends {
+            let actual = run(args, &script);
+            if actual != expected {
+                failures.push(format!(
+                    "{} ({backend}):\n--- expected\n{expected}--- actual\n{actual}",
+                    script.display()
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+// Scripts too large to check in: they exceed the 16-bit limits the VM's
+// chunk tables and local slots used to have.
+fn generated(name: &str, source: String) -> PathBuf {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn vm_runs_scripts_with_more_than_65536_chunk_entries() {
+    let body = "s = s + 1;\n".repeat(35_000);
+    let script = generated("long.lox", format!("var s = 0;\n{body}print s;\n"));
+    assert_eq!(run(&["--vm"], &script), "35000\nexit: 0\n");
+}
+
+#[test]
+fn vm_rejects_more_than_65536_locals() {
+    let locals: String = (0..65_540).map(|i| format!("var v{i} = {i};\n")).collect();
+    let script = generated("locals.lox", format!("{{\n{locals}print v65539;\n}}\n"));
+    let output = run(&["--vm"], &script);
+    assert!(
+        output.contains("Error: Too many local variables in function."),
+        "{output}"
+    );
+}
@@ -0,0 +1,62 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Runs a script with --profile and checks the report on stderr and the
+// folded stacks it writes next to where it runs. Times vary from run to run,
+// so only the call and run counts are compared.
+
+const SCRIPT: &str = "\
+fun f(n) {
+  var s = 0;
+  for (var i = 0; i < n; i = i + 1) s = s + i;
+  return s;
+}
+print f(10);
+print f(20);
+";
+
+// The leading columns of the report row that starts with `name`
+fn row(report: &str, name: &str, columns: usize) -> Vec<String> {
+    let line = report
+        .lines()
+        .find(|line| line.split_whitespace().next() == Some(name))
+        .unwrap_or_else(|| panic!("no row for {name} in:\n{report}"));
+    line.split_whitespace()
+        .take(columns)
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn profile_reports_functions_lines_and_folded_stacks() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("profile");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("script.lox"), SCRIPT).unwrap();
+    let _ = fs::remove_file(dir.join("script.folded"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lox-ast"))
+        .args(["--profile", "script.lox"])
+        .current_dir(&dir)
+        .output()
+        .expect("could not run lox-ast");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "45\n190\n");
+
+    let report = String::from_utf8_lossy(&output.stderr);
+    assert!(report.contains("function "), "{report}");
+    assert_eq!(row(&report, "f:1", 2), ["f:1", "2"]);
+    assert_eq!(row(&report, "<script>", 2), ["<script>", "1"]);
+    assert!(report.contains("line "), "{report}");
+    assert_eq!(row(&report, "2", 2), ["2", "2"]);
+    assert_eq!(row(&report, "6", 2), ["6", "1"]);
+    assert!(report.contains("Folded stacks written to script.folded"));
+
+    let folded = fs::read_to_string(dir.join("script.folded")).unwrap();
+    let stacks: Vec<&str> = folded
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().0)
+        .collect();
+    assert!(stacks.contains(&"<script>"), "{folded}");
+    assert!(stacks.contains(&"<script>;f:1"), "{folded}");
+}